@@ -0,0 +1,97 @@
+//! Parsing and comparison of Zig and ZLS version strings.
+
+use std::{cmp::Ordering, fmt};
+
+/// A `major.minor.patch` version with an optional pre-release part, as printed by
+/// `zig version` and `zls --version` (e.g. `0.14.0` or `0.15.0-dev.1234+abcdef`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Option<String>,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        // Build metadata doesn't take part in comparisons.
+        let version = version.split('+').next()?;
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre.to_string())),
+            None => (version, None),
+        };
+        let mut parts = release.split('.').map(str::parse::<u64>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    /// Whether both versions belong to the same `major.minor` release series.
+    ///
+    /// ZLS is released in lockstep with Zig, so this is what decides compatibility.
+    pub fn same_series(&self, other: &Self) -> bool {
+        self.major == other.major && self.minor == other.minor
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => compare_pre_release(a, b),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{pre}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares dot separated pre-release identifiers, numerically where both are numbers,
+/// so that `dev.10` sorts after `dev.9`.
+fn compare_pre_release(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => {
+                let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.cmp(b),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,269 @@
-use std::{fs, path::Path};
+mod version;
+mod zig_env;
+mod zon;
+
+use std::{
+    collections::HashMap,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use version::Version;
 use zed_extension_api::{self as zed, serde_json, settings::LspSettings, LanguageServerId, Result};
 
 const ZIG_TEST_EXE_BASENAME: &str = "zig_test";
+/// Passes the `<name>` of a `zig build run-<name>` task from the debug scenario to the
+/// locator; it's removed before the program is launched.
+const RUN_STEP_ENV: &str = "ZED_ZIG_RUN_STEP";
+/// Like [`RUN_STEP_ENV`] for any other step, whose name is only a guess of what it runs.
+const BUILD_STEP_ENV: &str = "ZED_ZIG_BUILD_STEP";
+/// Standard `zig build` steps that don't run a program.
+const NON_RUN_STEPS: [&str; 5] = ["install", "uninstall", "test", "docs", "check"];
+const CODELLDB_ADAPTER: &str = "CodeLLDB";
+const GDB_ADAPTER: &str = "GDB";
+
+/// The file in the work directory naming the `zls-*` directory chosen with `/zls-use`.
+const SELECTED_ZLS_FILE: &str = "selected-zls";
+/// The file in the work directory recording when each `zls-*` directory was last used.
+const ZLS_USAGE_FILE: &str = "usage.json";
+/// The file in the work directory caching the latest ZLS release and when it was looked up.
+const LATEST_RELEASE_FILE: &str = "latest-release.json";
+/// The file in the work directory keeping the resolved ZLS across reloads of the extension.
+const CACHED_BINARY_FILE: &str = "cached-binary.json";
+/// The directory in the work directory that test binaries for debugging are written to.
+const TEST_BINARY_DIR: &str = "test-binaries";
+
+const ZLS_DOWNLOAD_BASE: &str = "https://builds.zigtools.org";
+
+/// How many ZLS versions are kept installed, counting the one in use.
+const KEPT_ZLS_INSTALLS: usize = 3;
+
+/// How often a ZLS download is tried before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// The default of `download.timeout_secs`.
+const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
+
+/// Settings under `lsp.zls.settings` that configure the extension rather than ZLS. They're
+/// left out of the configuration sent to ZLS.
+const EXTENSION_SETTINGS: [&str; 11] = [
+    "auto_install",
+    "crash_loop",
+    "debug",
+    "download",
+    "installs",
+    "startup_probe",
+    "toolchain",
+    "zig",
+    "zig_version_check",
+    "zls_on_path_mismatch",
+    "zls_version",
+];
+
+const BINARY_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const OPTIMIZE_MODES: [&str; 4] = ["Debug", "ReleaseSafe", "ReleaseFast", "ReleaseSmall"];
+
+/// Optimize mode for debug builds per adapter. Both LLDB and GDB lose track of variables in
+/// optimized code, so they get unoptimized builds. Other adapters use the build's default.
+const ADAPTER_OPTIMIZE_MODES: &[(&str, &str)] =
+    &[(CODELLDB_ADAPTER, "Debug"), (GDB_ADAPTER, "Debug")];
 
 struct ZigExtension {
     cached_binary_path: Option<String>,
+    /// When `cached_binary_path` was resolved. Within [`BINARY_CACHE_TTL`] it's used without
+    /// probing any versions or asking GitHub for updates.
+    cached_binary_resolved_at: Option<Instant>,
+    /// The settings `cached_binary_path` was resolved with, see [`resolution_settings`].
+    cached_binary_settings: Option<String>,
+    /// The Zig version `cached_binary_path` was resolved for, while a binary restored from the
+    /// work directory hasn't been checked against `zig version` yet.
+    unchecked_zig_version: Option<Version>,
+    /// What the language server learned about every worktree, keyed by root path.
+    /// The DAP locator isn't handed a worktree, so this is how it gets to know about them.
+    worktrees: HashMap<String, WorktreeInfo>,
+    /// Executables listed by `zig build --help` per build directory, along with the hash of
+    /// the `build.zig` they were read from. `None` when there is no usable build file.
+    build_targets: HashMap<String, (Option<u64>, Option<Vec<String>>)>,
+    /// Hashes of the `build.zig` in every build directory, `None` where there is none. Files
+    /// can only be read through a worktree, so they are refreshed along with the settings.
+    build_file_hashes: HashMap<String, Option<u64>>,
+    /// Recent ZLS start times per worktree. Zed asks for the command on every restart, so
+    /// this is how a crashing ZLS shows up.
+    zls_starts: HashMap<String, Vec<Instant>>,
+    /// The Zig version last seen while resolving ZLS, to compare with the ZLS being started
+    /// without running `zig version` again.
+    zig_version: Option<Version>,
+}
+
+#[derive(Clone, Default)]
+struct WorktreeInfo {
+    debug_settings: DebugSettings,
+    zig_settings: ZigSettings,
+    /// The worktree's `build.zig.zon`, if it has a readable one.
+    manifest: Option<zon::Manifest>,
+    /// Hash of the worktree's `build.zig`, used to tell when cached build info is stale.
+    build_file_hash: Option<u64>,
+    /// Whether `build.zig` accepts `-Doptimize`, which `zig build` rejects otherwise.
+    has_optimize_option: bool,
+    /// Whether `build.zig` defines an executable, `None` without a readable `build.zig`.
+    has_executable: Option<bool>,
+    /// Names of the executables `build.zig` declares with a literal `.name`.
+    executables: Vec<String>,
+}
+
+/// Options for the `zig` commands the extension generates, from the `zig` object of the
+/// `zls` settings.
+#[derive(Clone, Default)]
+struct ZigSettings {
+    /// Passed as `--color <mode>`: `auto`, `on` or `off`.
+    color: Option<String>,
+    /// The `zig` executable from `zig.path`, with environment variables expanded.
+    path: Option<String>,
+}
+
+impl ZigSettings {
+    fn from_settings(settings: &serde_json::Value) -> Self {
+        let zig = &settings["zig"];
+        Self {
+            color: enum_setting(zig, "color", &["auto", "on", "off"]),
+            path: None,
+        }
+    }
+
+    /// The command for generated `zig` tasks.
+    fn command(&self) -> String {
+        self.path.clone().unwrap_or_else(|| "zig".into())
+    }
+
+    /// Adds the configured options to the arguments of a `zig` command, leaving alone any
+    /// the command already has.
+    fn apply(&self, args: &mut Vec<String>) {
+        // Arguments after `--` belong to the program being run.
+        let end = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        if let Some(color) = &self.color {
+            if !args[..end].iter().any(|arg| arg == "--color") {
+                args.splice(end..end, ["--color".into(), color.clone()]);
+            }
+        }
+    }
+}
+
+/// Debugger options from the `debug` object of the `zls` settings.
+#[derive(Clone, Default)]
+struct DebugSettings {
+    /// CodeLLDB's expression evaluator: `native`, `simple` or `python`.
+    expressions: Option<String>,
+    /// Where CodeLLDB runs the debuggee: `console`, `integrated` or `external`.
+    terminal: Option<String>,
+    /// Program launched after `zig build`, used verbatim instead of `zig-out/bin/<project>`.
+    program: Option<String>,
+    /// A `zig build` step to run along with the debug build, e.g. code generation.
+    pre_launch_task: Option<String>,
+    /// Name of the executable to debug when the build produces several.
+    exe_name: Option<String>,
+    /// Optimize mode for debug builds, overriding the adapter's default.
+    optimize: Option<String>,
+    /// The system debugger used by adapters that wrap one, like `gdb` for the GDB adapter.
+    debugger_path: Option<String>,
+    /// Working directory of the launched program, relative to the task's `cwd` unless absolute.
+    launch_cwd: Option<String>,
+    /// Directory of the `build.zig` to debug, relative to the worktree root, for projects that
+    /// aren't in the root.
+    build_dir: Option<String>,
+    /// Builds the test binary with `-fstrip`, which is faster but leaves little to debug.
+    test_strip: bool,
+    /// Runs the debuggee in a pseudo-terminal, for programs that only use colors on a TTY.
+    pty: bool,
+    /// Stops the debuggee as soon as it starts, for debugging startup code.
+    stop_on_entry: bool,
+}
+
+impl DebugSettings {
+    fn from_settings(settings: &serde_json::Value) -> Self {
+        let debug = &settings["debug"];
+        Self {
+            expressions: enum_setting(debug, "expressions", &["native", "simple", "python"]),
+            terminal: enum_setting(debug, "terminal", &["console", "integrated", "external"]),
+            program: string_setting(debug, "program"),
+            pre_launch_task: string_setting(debug, "pre_launch_task"),
+            exe_name: string_setting(debug, "exe_name"),
+            optimize: enum_setting(debug, "optimize", &OPTIMIZE_MODES),
+            debugger_path: string_setting(debug, "debugger_path"),
+            launch_cwd: string_setting(debug, "launch_cwd"),
+            build_dir: string_setting(debug, "build_dir"),
+            test_strip: debug["test_strip"].as_bool().unwrap_or(false),
+            pty: debug["pty"].as_bool().unwrap_or(false),
+            stop_on_entry: debug["stop_on_entry"].as_bool().unwrap_or(false),
+        }
+    }
+
+    /// The adapter specific part of a debug scenario's config.
+    fn adapter_config(&self, debug_adapter_name: &str) -> serde_json::Value {
+        let mut config = serde_json::Map::new();
+        match debug_adapter_name {
+            CODELLDB_ADAPTER => {
+                if let Some(expressions) = &self.expressions {
+                    config.insert("expressions".into(), expressions.clone().into());
+                }
+                // CodeLLDB's integrated terminal is a pseudo-terminal, its console is not.
+                match (&self.terminal, self.pty) {
+                    (Some(terminal), pty) => {
+                        if pty && terminal == "console" {
+                            eprintln!("`debug.pty` has no effect with `\"terminal\": \"console\"`");
+                        }
+                        config.insert("terminal".into(), terminal.clone().into());
+                    }
+                    (None, true) => {
+                        config.insert("terminal".into(), "integrated".into());
+                    }
+                    (None, false) => {}
+                }
+                if self.debugger_path.is_some() {
+                    eprintln!("`debug.debugger_path` is not supported by {CODELLDB_ADAPTER}");
+                }
+                if self.stop_on_entry {
+                    config.insert("stopOnEntry".into(), true.into());
+                }
+            }
+            GDB_ADAPTER => {
+                if let Some(path) = &self.debugger_path {
+                    match run_version_command(path, "--version") {
+                        Ok(_) => {
+                            config.insert("gdb_path".into(), path.clone().into());
+                        }
+                        Err(err) => eprintln!("ignoring `debug.debugger_path`: {err}"),
+                    }
+                }
+                // GDB evaluates expressions in the program's language and always runs the
+                // program in its own console, so CodeLLDB's options have no counterpart.
+                if self.expressions.is_some() {
+                    eprintln!("`debug.expressions` is not supported by {GDB_ADAPTER}");
+                }
+                if self.terminal.is_some() {
+                    eprintln!("`debug.terminal` is not supported by {GDB_ADAPTER}");
+                }
+                // Needs GDB 15, older versions ignore it.
+                if self.stop_on_entry {
+                    config.insert("stopOnEntry".into(), true.into());
+                }
+            }
+            _ => {}
+        }
+        if self.stop_on_entry && ![CODELLDB_ADAPTER, GDB_ADAPTER].contains(&debug_adapter_name) {
+            eprintln!("`debug.stop_on_entry` is not supported by {debug_adapter_name}");
+        }
+        if self.pty && debug_adapter_name != CODELLDB_ADAPTER {
+            eprintln!("`debug.pty` is not supported by {debug_adapter_name}");
+        }
+        if config.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::Value::Object(config)
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -12,51 +271,621 @@ struct ZlsBinary {
     path: String,
     args: Option<Vec<String>>,
     environment: Option<Vec<(String, String)>>,
+    /// Why this binary was chosen, for the log.
+    source: &'static str,
+}
+
+struct ZlsAsset {
+    version: String,
+    download_url: String,
+    /// The start of the asset's name on a GitHub release, e.g. `zls-x86_64-linux`.
+    github_asset_prefix: String,
+    version_dir: String,
+    binary_path: String,
+    file_type: zed::DownloadedFileType,
 }
 
 impl ZigExtension {
+    fn zls_settings(&mut self, worktree: &zed::Worktree) -> serde_json::Value {
+        let settings = LspSettings::for_worktree("zls", worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings)
+            .unwrap_or_default();
+
+        // Remote and virtual worktrees may not have a local root. There are no project files to
+        // look at then, and ZLS is resolved from the settings and PATH alone.
+        let root_path = worktree.root_path();
+        if root_path.is_empty() {
+            eprintln!(
+                "worktree {} has no local root, skipping project-local resolution",
+                worktree.id()
+            );
+            return settings;
+        }
+
+        let manifest = worktree
+            .read_text_file("build.zig.zon")
+            .ok()
+            .and_then(|source| zon::Manifest::parse(&source));
+        let build_file = worktree.read_text_file("build.zig").ok();
+        let build_file_hash = build_file.as_deref().map(hash_source);
+        let has_optimize_option = build_file
+            .as_ref()
+            .is_some_and(|source| source.contains("standardOptimizeOption"));
+        let has_executable = build_file
+            .as_ref()
+            .map(|source| source.contains("addExecutable"));
+        let executables = build_file
+            .as_deref()
+            .map(parse_executable_names)
+            .unwrap_or_default();
+        let mut zig_settings = ZigSettings::from_settings(&settings);
+        zig_settings.path = configured_zig_path(worktree, &settings);
+
+        // The locator has no worktree to read the build files of the directories it asked
+        // about, so they are looked at again here.
+        let build_dirs: Vec<String> = self
+            .build_targets
+            .keys()
+            .filter(|dir| Path::new(dir).starts_with(&root_path) && **dir != root_path)
+            .cloned()
+            .collect();
+        for dir in build_dirs {
+            let Ok(relative) = Path::new(&dir).strip_prefix(&root_path) else {
+                continue;
+            };
+            let build_file = relative.join("build.zig");
+            let hash = worktree
+                .read_text_file(&build_file.to_string_lossy())
+                .ok()
+                .as_deref()
+                .map(hash_source);
+            self.build_file_hashes.insert(dir, hash);
+        }
+        self.build_file_hashes
+            .insert(root_path.clone(), build_file_hash);
+
+        self.worktrees.insert(
+            root_path,
+            WorktreeInfo {
+                debug_settings: DebugSettings::from_settings(&settings),
+                zig_settings,
+                manifest,
+                build_file_hash,
+                has_optimize_option,
+                has_executable,
+                executables,
+            },
+        );
+        settings
+    }
+
+    /// Finds the worktree containing `cwd`.
+    fn worktree_for(&self, cwd: Option<&str>) -> Option<(&str, &WorktreeInfo)> {
+        let worktree = match cwd {
+            Some(cwd) => self
+                .worktrees
+                .iter()
+                .filter(|(root, _)| Path::new(cwd).starts_with(root))
+                .max_by_key(|(root, _)| root.len()),
+            None if self.worktrees.len() == 1 => self.worktrees.iter().next(),
+            None => None,
+        };
+        worktree.map(|(root, info)| (root.as_str(), info))
+    }
+
+    /// The directory of the `build.zig` that `zig build` in `cwd` uses. Like Zig, this looks in
+    /// `cwd` and then its parents, up to the worktree root.
+    fn find_build_dir(&mut self, cwd: &str) -> Option<String> {
+        let (root, root_has_build_file) = self
+            .worktree_for(Some(cwd))
+            .map(|(root, info)| (root.to_string(), info.build_file_hash.is_some()))?;
+        let mut dir = Path::new(cwd);
+        loop {
+            if dir == Path::new(&root) {
+                return root_has_build_file.then_some(root);
+            }
+            let dir_name = dir.to_string_lossy().into_owned();
+            if self.build_targets(&dir_name).is_some() {
+                return Some(dir_name);
+            }
+            dir = dir.parent().filter(|parent| parent.starts_with(&root))?;
+        }
+    }
+
+    fn debug_settings_for(&self, cwd: Option<&str>) -> DebugSettings {
+        self.worktree_for(cwd)
+            .map(|(_, info)| info.debug_settings.clone())
+            .unwrap_or_default()
+    }
+
+    /// The name of the project built by `task`.
+    ///
+    /// This is the `.name` from `build.zig.zon` when the task runs in the root of a worktree
+    /// and falls back to the name of the task's directory.
+    fn project_name(&self, task: &zed::TaskTemplate) -> Option<String> {
+        let manifest_name = self
+            .worktree_for(task.cwd.as_deref())
+            .filter(|(root, _)| task.cwd.as_deref().map(Path::new) == Some(Path::new(root)))
+            .and_then(|(_, info)| info.manifest.as_ref()?.name.clone());
+        manifest_name.or_else(|| get_project_name(task))
+    }
+
+    /// The executables `zig build` in `cwd` can produce, cached until the `build.zig` in `cwd`
+    /// changes.
+    ///
+    /// `None` when `zig build --help` fails, e.g. because there is no `build.zig` in `cwd`.
+    fn build_targets(&mut self, cwd: &str) -> Option<Vec<String>> {
+        let zig = match self.worktree_for(Some(cwd)) {
+            Some((_, info)) => info.zig_settings.command(),
+            None => "zig".into(),
+        };
+        // A directory that wasn't seen with a worktree yet has no known hash. Its targets are
+        // reused until the settings are read again, which tells whether it changed.
+        let build_file_hash = self.build_file_hashes.get(cwd).copied();
+        if let Some((hash, targets)) = self.build_targets.get(cwd) {
+            if build_file_hash.is_none_or(|current| current == *hash) {
+                return targets.clone();
+            }
+        }
+
+        let build_file = Path::new(cwd).join("build.zig");
+        let output = zed::process::Command::new(zig)
+            .args(["build", "--build-file"])
+            .arg(build_file.to_string_lossy())
+            .arg("--help")
+            .output();
+        let targets = match output {
+            Ok(output) if output.status == Some(0) => Some(parse_build_targets(
+                &String::from_utf8_lossy(&output.stdout),
+            )),
+            Ok(output) => {
+                eprintln!(
+                    "`zig build --help` failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                None
+            }
+            Err(err) => {
+                eprintln!("failed to run `zig build --help`: {err}");
+                None
+            }
+        };
+        self.build_targets.insert(
+            cwd.to_string(),
+            (build_file_hash.flatten(), targets.clone()),
+        );
+        targets
+    }
+
+    /// Everything the extension resolved for `worktree`, for attaching to bug reports.
+    ///
+    /// Unlike the language server paths this never downloads anything.
+    fn effective_configuration(&self, worktree: &zed::Worktree) -> serde_json::Value {
+        let (platform, arch) = zed::current_platform();
+        let lsp_settings = LspSettings::for_worktree("zls", worktree).unwrap_or_default();
+        let settings = lsp_settings.settings.clone().unwrap_or_default();
+
+        let (zls_source, zls_path) =
+            if let Some(path) = lsp_settings.binary.as_ref().and_then(|b| b.path.clone()) {
+                ("settings", Some(path))
+            } else if let Some(path) = worktree.which("zls") {
+                ("path", Some(path))
+            } else if let Some(path) = self
+                .cached_binary_path
+                .clone()
+                .or_else(|| installed_zls_binary(platform, self.zig_version.as_ref()))
+            {
+                ("download", Some(absolute_work_path(&path)))
+            } else {
+                ("none", None)
+            };
+        let zls_version = zls_path
+            .as_deref()
+            .and_then(|path| run_version_command(path, "--version").ok());
+
+        let zig_path = zig_executable(worktree, &settings);
+        let zig_version = zig_path
+            .as_deref()
+            .and_then(|zig| run_version_command(zig, "version").ok());
+
+        let download_url = pinned_toolchain_version(&settings)
+            .ok()
+            .flatten()
+            .map(|version| version.to_string())
+            .or_else(|| zls_version.clone())
+            .and_then(|version| {
+                Some(
+                    zls_asset(platform, arch, &version, &zls_download_base(&settings))
+                        .ok()?
+                        .download_url,
+                )
+            });
+
+        let tasks: Vec<String> = serde_json::from_str::<Vec<serde_json::Value>>(include_str!(
+            "../languages/zig/tasks.json"
+        ))
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|task| task["label"].as_str().map(String::from))
+        .collect();
+
+        let worktree_info = self.worktrees.get(&worktree.root_path());
+        let mut config = serde_json::json!({
+            "platform": { "os": format!("{platform:?}"), "arch": format!("{arch:?}") },
+            "zls": {
+                "source": zls_source,
+                "path": zls_path,
+                "version": zls_version,
+                "arguments": lsp_settings.binary.as_ref().and_then(|b| b.arguments.clone()),
+                "download_url": download_url,
+            },
+            "zig": { "path": zig_path, "version": zig_version },
+            "workspace_configuration": workspace_configuration(worktree, settings),
+            "tasks": tasks,
+            "locator": {
+                "known_worktree": worktree_info.is_some(),
+                "project_name": worktree_info.and_then(|info| info.manifest.as_ref()?.name.clone()),
+                "fingerprint": worktree_info.and_then(|info| info.manifest.as_ref()?.fingerprint.clone()),
+            },
+        });
+        redact_secrets(&mut config);
+        config
+    }
+
+    /// Refuses to start ZLS again when it has been restarted too often in a short time.
+    ///
+    /// Zed would otherwise keep restarting a crashing ZLS, showing a new cryptic error every
+    /// time. The limits come from `crash_loop.max_restarts` and `crash_loop.window_secs`. The
+    /// history is reset once tripped, so restarting the server by hand works right away.
+    fn check_crash_loop(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &serde_json::Value,
+        zls_path: &str,
+    ) -> Result<()> {
+        let crash_loop = &settings["crash_loop"];
+        let max_restarts = crash_loop["max_restarts"].as_u64().unwrap_or(5) as usize;
+        let window = Duration::from_secs(crash_loop["window_secs"].as_u64().unwrap_or(60));
+        if max_restarts == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let starts = self.zls_starts.entry(worktree.root_path()).or_default();
+        starts.retain(|start| now.duration_since(*start) < window);
+        starts.push(now);
+        if starts.len() <= max_restarts {
+            return Ok(());
+        }
+        let restarts = starts.len() - 1;
+        starts.clear();
+
+        // The output of the crashed process isn't available to extensions, running the binary
+        // once more is the closest we can get to its error.
+        let zls_version = run_version_command(&absolute_work_path(zls_path), "--version");
+        let output = match &zls_version {
+            Ok(version) => format!("`zls --version` works and reports {version}"),
+            Err(err) => err.clone(),
+        };
+        // A ZLS that starts but quits during initialization is most often one made for another
+        // Zig release, which is worth pointing out over the generic advice.
+        let zig_version = zig_executable(worktree, settings)
+            .and_then(|zig| Version::parse(&run_version_command(&zig, "version").ok()?));
+        let hint = match (
+            zls_version.as_deref().ok().and_then(Version::parse),
+            zig_version,
+        ) {
+            (Some(zls), Some(zig)) => incompatibility_hint(&zls, &zig),
+            _ => None,
+        };
+        let message = format!(
+            "ZLS was restarted {restarts} times within {}s and seems to be crashing, so it \
+             won't be restarted automatically. Last output: {output}. {}Check `/zls-log` for \
+             details, then restart the language server.",
+            window.as_secs(),
+            hint.map(|hint| format!("{hint} ")).unwrap_or_default()
+        );
+        eprintln!("{message}");
+        Err(message)
+    }
+
+    /// Compares the `zig` on PATH with the version the project asks for, through `.zig-version`
+    /// or `minimum_zig_version` in `build.zig.zon`. A mismatch is logged, or stops ZLS from
+    /// starting with `"zig_version_check": "block"`.
+    fn check_project_zig_version(
+        &self,
+        worktree: &zed::Worktree,
+        settings: &serde_json::Value,
+    ) -> Result<()> {
+        let policy = enum_setting(settings, "zig_version_check", &["warn", "block", "off"]);
+        if policy.as_deref() == Some("off") {
+            return Ok(());
+        }
+        let Some(zig) = zig_executable(worktree, settings) else {
+            return Ok(());
+        };
+
+        let pinned = worktree
+            .read_text_file(".zig-version")
+            .ok()
+            .and_then(|version| Version::parse(&version));
+        let minimum = self
+            .worktrees
+            .get(&worktree.root_path())
+            .and_then(|info| info.manifest.as_ref()?.minimum_zig_version.as_deref())
+            .and_then(Version::parse);
+        if pinned.is_none() && minimum.is_none() {
+            return Ok(());
+        }
+
+        let Some(found) = zig_env::detect(&zig)
+            .ok()
+            .and_then(|env| Version::parse(env.version.as_deref()?))
+        else {
+            return Ok(());
+        };
+
+        let warning = project_zig_version_problem(
+            &zig,
+            &found,
+            pinned.as_ref(),
+            minimum.as_ref(),
+            policy.as_deref(),
+        )?;
+        if let Some(warning) = warning {
+            eprintln!("warning: {warning}");
+        }
+        Ok(())
+    }
+
+    fn cache_binary(&mut self, path: &str) {
+        self.cached_binary_path = Some(path.to_string());
+        self.cached_binary_resolved_at = Some(Instant::now());
+        record_zls_use(path);
+        let cached = serde_json::json!({
+            "path": path,
+            "resolved_at": unix_time(),
+            "settings": self.cached_binary_settings,
+            "zig_version": self.zig_version.as_ref().map(Version::to_string),
+        });
+        if let Err(err) = fs::write(CACHED_BINARY_FILE, cached.to_string()) {
+            eprintln!("failed to remember the resolved ZLS: {err}");
+        }
+    }
+
+    /// Restores what [`Self::cache_binary`] saved before the extension was reloaded. Whether
+    /// the binary is still there is checked when it's used, like for any cached binary.
+    fn restore_cached_binary(&mut self) {
+        let Some(cached) = fs::read_to_string(CACHED_BINARY_FILE)
+            .ok()
+            .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+        else {
+            return;
+        };
+        let (Some(path), Some(resolved_at)) =
+            (cached["path"].as_str(), cached["resolved_at"].as_u64())
+        else {
+            return;
+        };
+        let age = Duration::from_secs(unix_time().saturating_sub(resolved_at));
+        let Some(resolved_at) = Instant::now().checked_sub(age) else {
+            return;
+        };
+        self.cached_binary_path = Some(path.to_string());
+        self.cached_binary_resolved_at = Some(resolved_at);
+        self.cached_binary_settings = cached["settings"].as_str().map(String::from);
+        if let Some(version) = cached["zig_version"].as_str().and_then(Version::parse) {
+            self.zig_version = Some(version.clone());
+            self.unchecked_zig_version = Some(version);
+        }
+    }
+
+    /// The cached binary, if it was resolved recently and is still there. `zls_on_path` is the
+    /// `zls` found on PATH.
+    fn fresh_cached_binary(&self, zls_on_path: Option<&str>) -> Option<String> {
+        let path = self.cached_binary_path.as_ref()?;
+        if self.cached_binary_resolved_at?.elapsed() >= BINARY_CACHE_TTL {
+            return None;
+        }
+        // Binaries on PATH live outside of the work directory and can't be inspected.
+        let exists = is_usable_binary(path) || zls_on_path == Some(path.as_str());
+        exists.then(|| path.clone())
+    }
+
     fn language_server_binary(
         &mut self,
         language_server_id: &LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<ZlsBinary> {
-        let mut args: Option<Vec<String>> = None;
-
         let (platform, arch) = zed::current_platform();
         let environment = match platform {
             zed::Os::Mac | zed::Os::Linux => Some(worktree.shell_env()),
             zed::Os::Windows => None,
         };
 
-        if let Ok(lsp_settings) = LspSettings::for_worktree("zls", worktree) {
-            if let Some(binary) = lsp_settings.binary {
-                args = binary.arguments;
-                if let Some(path) = binary.path {
-                    return Ok(ZlsBinary {
-                        path: path.clone(),
-                        args,
-                        environment,
-                    });
+        let lsp_settings = LspSettings::for_worktree("zls", worktree).unwrap_or_default();
+        let settings = lsp_settings.settings.unwrap_or_default();
+        let (path, args) = binary_settings(lsp_settings.binary);
+        if let Some(path) = path {
+            let path = expand_env_vars(&path, &worktree.shell_env());
+            check_configured_zls(&path)?;
+            return Ok(ZlsBinary {
+                path,
+                args,
+                environment,
+                source: "the `binary.path` setting",
+            });
+        }
+
+        if let Some(path) = selected_zls_binary(platform) {
+            eprintln!("using {path} selected with /zls-use");
+            self.cache_binary(&path);
+            return Ok(ZlsBinary {
+                path,
+                args,
+                environment,
+                source: "/zls-use",
+            });
+        }
+
+        // The cached binary was found with the settings at the time, look again if they changed.
+        let snapshot = resolution_settings(&settings, worktree.which("zig"), worktree.which("zls"));
+        if self.cached_binary_settings.as_ref() != Some(&snapshot) {
+            if self.cached_binary_path.take().is_some() {
+                eprintln!("ZLS settings changed, resolving ZLS again");
+            }
+            self.cached_binary_settings = Some(snapshot);
+        }
+
+        // Upgrading Zig in place keeps its path, so a binary remembered from another session is
+        // only used again if `zig version` still matches.
+        if let Some(expected) = self.unchecked_zig_version.take() {
+            if pinned_toolchain_version(&settings).ok().flatten().is_none() {
+                let current = zig_executable(worktree, &settings)
+                    .and_then(|zig| Version::parse(&run_version_command(&zig, "version").ok()?));
+                if current.as_ref() != Some(&expected) {
+                    eprintln!(
+                        "zig is {} now instead of {expected}, resolving ZLS again",
+                        current.map_or("missing".into(), |version| version.to_string())
+                    );
+                    self.cached_binary_path = None;
                 }
             }
         }
 
+        if let Some(path) = self.fresh_cached_binary(worktree.which("zls").as_deref()) {
+            return Ok(ZlsBinary {
+                path,
+                args,
+                environment,
+                source: "the cache",
+            });
+        }
+
+        let pinned_version = pinned_toolchain_version(&settings)?;
+        // A pinned toolchain replaces asking `zig` for its version.
+        let detected_zig_version = match pinned_version {
+            Some(_) => None,
+            None => zig_executable(worktree, &settings)
+                .and_then(|zig| Version::parse(&run_version_command(&zig, "version").ok()?)),
+        };
+        if let Some(version) = pinned_version.as_ref().or(detected_zig_version.as_ref()) {
+            self.zig_version = Some(version.clone());
+        }
+
+        let requested_version = requested_zls_version(&settings)?;
+        if let Some(requested) = requested_version.as_deref().filter(|v| *v != "latest") {
+            let version = match requested {
+                "master" => {
+                    let installed = installed_zls_binary(platform, self.zig_version.as_ref());
+                    match master_zls_version(worktree, &settings) {
+                        Ok(version) => version,
+                        // Being offline shouldn't keep an installed ZLS from starting.
+                        Err(err) => match installed {
+                            Some(path) => {
+                                eprintln!("{err}, using the installed {path}");
+                                self.cache_binary(&path);
+                                return Ok(ZlsBinary {
+                                    path,
+                                    args,
+                                    environment,
+                                    source: "an installed ZLS, as the master build couldn't be looked up",
+                                });
+                            }
+                            None => return Err(err),
+                        },
+                    }
+                }
+                version => version.to_string(),
+            };
+            let version = version.as_str();
+            let asset = zls_asset(platform, arch, version, &zls_download_base(&settings))?;
+            let path = self
+                .install_zls(language_server_id, worktree, &settings, asset)
+                .map_err(|err| {
+                    format!("failed to install ZLS {version} from `zls_version`: {err}")
+                })?;
+            return Ok(ZlsBinary {
+                path,
+                args,
+                environment,
+                source: "the `zls_version` setting",
+            });
+        }
+
+        let auto_install = settings["auto_install"].as_bool().unwrap_or(true);
         if let Some(path) = worktree.which("zls") {
+            let zig_version = pinned_version.clone().or(detected_zig_version.clone());
+            // Without downloads, a mismatching ZLS on the PATH is still better than none.
+            if accept_zls_on_path(&path, zig_version, &settings) || !auto_install {
+                self.cache_binary(&path);
+                return Ok(ZlsBinary {
+                    path,
+                    args,
+                    environment,
+                    source: "the PATH",
+                });
+            }
+        }
+
+        if !auto_install {
+            return Err(
+                "ZLS was not found and downloading it is disabled with `\"auto_install\": false`. \
+                 Put `zls` on the PATH or point `lsp.zls.binary.path` at it."
+                    .into(),
+            );
+        }
+
+        if let Some(version) = pinned_version {
+            let asset = zls_asset(
+                platform,
+                arch,
+                &version.to_string(),
+                &zls_download_base(&settings),
+            )?;
+            if !is_usable_binary(&asset.binary_path) {
+                zed::set_language_server_installation_status(
+                    language_server_id,
+                    &zed::LanguageServerInstallationStatus::CheckingForUpdate,
+                );
+                zed::github_release_by_tag_name("zigtools/zls", &version.to_string()).map_err(
+                    |err| {
+                        network_error(
+                            &format!(
+                                "no ZLS release matches the pinned toolchain version {version}"
+                            ),
+                            err,
+                            &worktree.shell_env(),
+                        )
+                    },
+                )?;
+            }
+            let path = self.install_zls(language_server_id, worktree, &settings, asset)?;
             return Ok(ZlsBinary {
                 path,
                 args,
                 environment,
+                source: "the pinned toolchain",
             });
         }
 
-        if let Some(path) = &self.cached_binary_path {
-            if fs::metadata(path).is_ok_and(|stat| stat.is_file()) {
+        if let Some(policy) = deferred_download_policy(&settings) {
+            if let Some(path) = installed_zls_binary(platform, self.zig_version.as_ref()) {
+                eprintln!("connection is metered, using the installed {path} instead of updating");
+                self.cache_binary(&path);
                 return Ok(ZlsBinary {
-                    path: path.clone(),
+                    path,
                     args,
                     environment,
+                    source: "an installed ZLS, as the connection is metered",
                 });
             }
+            return Err(format!(
+                "ZLS is not installed and downloading it was deferred because the connection is \
+                 marked as metered (`\"download\": {{ \"metered\": true, \"on_metered\": \"{policy}\" }}`). \
+                 Set `download.on_metered` to `\"allow\"` to download ZLS anyway."
+            ));
         }
 
         zed::set_language_server_installation_status(
@@ -64,97 +893,1355 @@ impl ZigExtension {
             &zed::LanguageServerInstallationStatus::CheckingForUpdate,
         );
 
-        // Note that in github releases and on zlstools.org the tar.gz asset is not shown
-        // but is available at https://builds.zigtools.org/zls-{os}-{arch}-{version}.tar.gz
-        let release = zed::latest_github_release(
+        // The latest ZLS only works with the latest Zig, so match the one in use if possible,
+        // unless the latest was asked for explicitly.
+        let match_zig = requested_version.is_none();
+        if let Some(zig_version) = detected_zig_version.as_ref().filter(|_| match_zig) {
+            if let Some(version) = matching_zls_version(platform, arch, zig_version) {
+                eprintln!("using ZLS {version} for zig {zig_version}");
+                let path = self.install_zls(
+                    language_server_id,
+                    worktree,
+                    &settings,
+                    zls_asset(platform, arch, &version, &zls_download_base(&settings))?,
+                )?;
+                return Ok(ZlsBinary {
+                    path,
+                    args,
+                    environment,
+                    source: "the version of zig",
+                });
+            }
+            eprintln!("no ZLS release matches zig {zig_version}, using the latest ZLS");
+        }
+
+        // The lookup is remembered across reloads of the extension to spare GitHub's rate limit.
+        if let Some(version) = recent_latest_release(unix_time()) {
+            let asset = zls_asset(platform, arch, &version, &zls_download_base(&settings))?;
+            if is_usable_binary(&asset.binary_path) {
+                eprintln!(
+                    "ZLS {version} was the latest release when last checked, not checking again"
+                );
+                self.cache_binary(&asset.binary_path);
+                return Ok(ZlsBinary {
+                    path: asset.binary_path,
+                    args,
+                    environment,
+                    source: "the latest release when last checked",
+                });
+            }
+        }
+
+        let release = match zed::latest_github_release(
             "zigtools/zls",
             zed::GithubReleaseOptions {
                 require_assets: true,
                 pre_release: false,
             },
-        )?;
-
-        let arch: &str = match arch {
-            zed::Architecture::Aarch64 => "aarch64",
-            zed::Architecture::X86 => "x86",
-            zed::Architecture::X8664 => "x86_64",
-        };
-
-        let os: &str = match platform {
-            zed::Os::Mac => "macos",
-            zed::Os::Linux => "linux",
-            zed::Os::Windows => "windows",
-        };
-
-        let extension: &str = match platform {
-            zed::Os::Mac | zed::Os::Linux => "tar.gz",
-            zed::Os::Windows => "zip",
+        ) {
+            Ok(release) => {
+                record_latest_release(&release.version);
+                release
+            }
+            // Being offline shouldn't keep an installed ZLS from starting.
+            Err(err) => match installed_zls_binary(platform, self.zig_version.as_ref()) {
+                Some(path) => {
+                    eprintln!("failed to check for ZLS updates, using the installed {path}: {err}");
+                    self.cache_binary(&path);
+                    return Ok(ZlsBinary {
+                        path,
+                        args,
+                        environment,
+                        source: "an installed ZLS, as GitHub couldn't be reached",
+                    });
+                }
+                None => {
+                    return Err(network_error(
+                        "failed to look up the latest ZLS release",
+                        err,
+                        &worktree.shell_env(),
+                    ))
+                }
+            },
         };
 
-        let asset_name: String = format!("zls-{}-{}-{}.{}", arch, os, release.version, extension);
-        let download_url = format!("https://builds.zigtools.org/{}", asset_name);
+        let binary_path = self.install_zls(
+            language_server_id,
+            worktree,
+            &settings,
+            zls_asset(
+                platform,
+                arch,
+                &release.version,
+                &zls_download_base(&settings),
+            )?,
+        )?;
+        Ok(ZlsBinary {
+            path: binary_path,
+            args,
+            environment,
+            source: "the latest release",
+        })
+    }
 
-        let version_dir = format!("zls-{}", release.version);
-        let binary_path = match platform {
-            zed::Os::Mac | zed::Os::Linux => format!("{version_dir}/zls"),
-            zed::Os::Windows => format!("{version_dir}/zls.exe"),
-        };
+    /// Downloads `asset` unless it's already present, removes other versions and caches the
+    /// path of the binary.
+    fn install_zls(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        worktree: &zed::Worktree,
+        settings: &serde_json::Value,
+        asset: ZlsAsset,
+    ) -> Result<String> {
+        let ZlsAsset {
+            version,
+            download_url,
+            github_asset_prefix,
+            version_dir,
+            binary_path,
+            file_type,
+        } = asset;
 
-        if !fs::metadata(&binary_path).is_ok_and(|stat| stat.is_file()) {
+        if !is_usable_binary(&binary_path) {
+            // An empty or truncated binary left by an interrupted download would otherwise be
+            // found again on every start.
+            if fs::metadata(&version_dir).is_ok() {
+                eprintln!("{binary_path} is incomplete, downloading it again");
+                fs::remove_dir_all(&version_dir).ok();
+            }
+            if !settings["auto_install"].as_bool().unwrap_or(true) {
+                return Err(format!(
+                    "ZLS {version} is not installed and downloading it is disabled with \
+                     `\"auto_install\": false`"
+                ));
+            }
+            // Extensions resolve the language server synchronously, so ZLS starts once the
+            // download finishes. Only this language server waits; the editor stays usable.
+            // The status has no room for progress or text, and neither the download nor the
+            // release assets report a size, so what's fetched is only in the log.
+            eprintln!(
+                "downloading {version_dir} from {download_url}; ZLS starts once it is installed"
+            );
             zed::set_language_server_installation_status(
                 language_server_id,
                 &zed::LanguageServerInstallationStatus::Downloading,
             );
 
-            zed::download_file(
+            let mirror = settings["download"]["mirror"].is_string();
+            if let Err((e, attempts)) = download_with_retries(
                 &download_url,
                 &version_dir,
-                match platform {
-                    zed::Os::Mac | zed::Os::Linux => zed::DownloadedFileType::GzipTar,
-                    zed::Os::Windows => zed::DownloadedFileType::Zip,
-                },
-            )
-            .map_err(|e| format!("failed to download file: {e}"))?;
+                file_type,
+                download_timeout(settings),
+            ) {
+                let missing = is_missing_file(&e);
+                let error = network_error(
+                    &format!(
+                        "failed to download {download_url}{} after {attempts} attempt(s)",
+                        if mirror {
+                            " from the `download.mirror`"
+                        } else {
+                            ""
+                        }
+                    ),
+                    e,
+                    &worktree.shell_env(),
+                );
+                // builds.zigtools.org can be down or, right after a release, not have the
+                // version yet; on Windows both are covered by the release on GitHub, elsewhere
+                // its archives can't be extracted. A configured mirror is usually there because
+                // GitHub can't be reached.
+                let downloaded = if mirror {
+                    Err(error)
+                } else {
+                    eprintln!("{error}; trying the GitHub release instead");
+                    download_github_asset(
+                        &version,
+                        &github_asset_prefix,
+                        &version_dir,
+                        download_timeout(settings),
+                    )
+                    .map_err(|(url, e)| {
+                        let fallback = network_error(
+                            &format!("failed to download {url}"),
+                            e,
+                            &worktree.shell_env(),
+                        );
+                        format!("{error}; {fallback}")
+                    })
+                };
+                if let Err(error) = downloaded {
+                    // When there's no Apple Silicon build (yet), the x86_64 one still runs under
+                    // Rosetta, only slower.
+                    let (platform, arch) = zed::current_platform();
+                    if !missing || platform != zed::Os::Mac || arch != zed::Architecture::Aarch64 {
+                        return Err(error);
+                    }
+                    let emulated = zls_asset(
+                        platform,
+                        zed::Architecture::X8664,
+                        &version,
+                        &zls_download_base(settings),
+                    )?;
+                    eprintln!("{error}; trying the x86_64 build, which runs under Rosetta");
+                    download_with_retries(
+                        &emulated.download_url,
+                        &version_dir,
+                        emulated.file_type,
+                        download_timeout(settings),
+                    )
+                    .map_err(|(e, _)| {
+                        let fallback = network_error(
+                            &format!("failed to download {}", emulated.download_url),
+                            e,
+                            &worktree.shell_env(),
+                        );
+                        format!("{error}; {fallback}")
+                    })?;
+                    eprintln!(
+                        "using the x86_64 build of ZLS {version} under Rosetta, run \
+                         /zls-reinstall once there is an Apple Silicon build"
+                    );
+                }
+            }
 
+            locate_extracted_binary(&version_dir, &binary_path)?;
             zed::make_file_executable(&binary_path)?;
 
-            let entries =
-                fs::read_dir(".").map_err(|e| format!("failed to list working directory {e}"))?;
-            for entry in entries {
-                let entry = entry.map_err(|e| format!("failed to load directory entry {e}"))?;
-                if entry.file_name().to_str() != Some(&version_dir) {
-                    fs::remove_dir_all(entry.path()).ok();
-                }
+            // This is a sanity check, not a verification: `download_file` extracts the archive
+            // itself and doesn't expose it, and outside of Windows the checksums zigtools
+            // publishes are for `.tar.xz` archives, not the `.tar.gz` ones used here. A
+            // truncated or corrupt download shows up as a binary that doesn't run, which is
+            // caught instead of leaving it for ZLS to fail to start with a confusing error.
+            if let Err(err) = run_version_command(&absolute_work_path(&binary_path), "--version") {
+                fs::remove_dir_all(&version_dir).ok();
+                return Err(format!(
+                    "the ZLS downloaded from {download_url} doesn't run and was removed, the \
+                     download may be incomplete or corrupt: {err}"
+                ));
             }
+
+            let grace_days = settings["installs"]["grace_days"].as_u64().unwrap_or(0);
+            prune_zls_installs(&version_dir, Duration::from_secs(grace_days * 24 * 60 * 60))?;
         }
 
-        self.cached_binary_path = Some(binary_path.clone());
-        Ok(ZlsBinary {
-            path: binary_path,
-            args,
-            environment,
-        })
+        self.cache_binary(&binary_path);
+        Ok(binary_path)
     }
 }
 
-impl zed::Extension for ZigExtension {
-    fn new() -> Self {
-        Self {
-            cached_binary_path: None,
-        }
+struct DoctorReport {
+    text: String,
+}
+
+impl DoctorReport {
+    fn step(&mut self, name: &str, outcome: Result<String>) -> bool {
+        let (line, ok) = match outcome {
+            Ok(detail) => (format!("[ok] {name}: {detail}"), true),
+            Err(err) => (format!("[failed] {name}: {err}"), false),
+        };
+        self.push_line(&line);
+        ok
     }
 
-    fn language_server_command(
-        &mut self,
-        language_server_id: &LanguageServerId,
+    /// Reports a step that isn't run at all, which is neither a success nor a failure.
+    fn skip(&mut self, name: &str, reason: &str) {
+        self.push_line(&format!("[skipped] {name}: {reason}"));
+    }
+
+    fn push_line(&mut self, line: &str) {
+        eprintln!("zls doctor: {line}");
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+}
+
+/// Performs the full ZLS resolution once and reports the result of every step.
+///
+/// Nothing here touches the extension state, and previously downloaded versions
+/// are never removed, so a running language server is left alone.
+fn zls_doctor(worktree: &zed::Worktree) -> String {
+    let mut report = DoctorReport {
+        text: String::new(),
+    };
+
+    let (platform, arch) = zed::current_platform();
+    report.step("platform", Ok(format!("{platform:?} {arch:?}")));
+
+    let settings = LspSettings::for_worktree("zls", worktree)
+        .ok()
+        .and_then(|lsp_settings| lsp_settings.settings)
+        .unwrap_or_default();
+    let zig = zig_executable(worktree, &settings);
+    let zig_output = zig
+        .as_deref()
+        .ok_or_else(|| "`zig` not found on PATH".to_string())
+        .and_then(|zig| run_version_command(zig, "version"));
+    let zig_version = zig_output.as_deref().ok().and_then(Version::parse);
+    report.step(
+        "zig version",
+        zig_output.map(|version| format!("{version} ({})", zig.unwrap_or_default())),
+    );
+
+    let configured_path = LspSettings::for_worktree("zls", worktree)
+        .ok()
+        .and_then(|lsp_settings| lsp_settings.binary)
+        .and_then(|binary| binary.path)
+        .map(|path| expand_env_vars(&path, &worktree.shell_env()));
+
+    let zls_path = if let Some(path) = configured_path {
+        report.step("configured binary.path", Ok(path.clone()));
+        path
+    } else if let Some(path) = worktree.which("zls") {
+        report.step("zls on PATH", Ok(path.clone()));
+        path
+    } else if let Some(path) = installed_zls_binary(platform, zig_version.as_ref()) {
+        report.step("managed install", Ok(path.clone()));
+        path
+    } else if let Some(reason) = download_skip_reason(&settings) {
+        // The doctor downloads only what starting the language server would.
+        report.skip("download", &reason);
+        return report.text;
+    } else {
+        let release = match zed::latest_github_release(
+            "zigtools/zls",
+            zed::GithubReleaseOptions {
+                require_assets: true,
+                pre_release: false,
+            },
+        ) {
+            Ok(release) => release,
+            Err(err) => {
+                report.step(
+                    "latest release",
+                    Err(network_error("lookup failed", err, &worktree.shell_env())),
+                );
+                return report.text;
+            }
+        };
+        report.step("latest release", Ok(release.version.clone()));
+
+        let asset = match zls_asset(
+            platform,
+            arch,
+            &release.version,
+            &zls_download_base(&settings),
+        ) {
+            Ok(asset) => asset,
+            Err(err) => {
+                report.step("asset", Err(err));
+                return report.text;
+            }
+        };
+        let downloaded = download_with_retries(
+            &asset.download_url,
+            &asset.version_dir,
+            asset.file_type,
+            download_timeout(&settings),
+        )
+        .map_err(|(e, _)| network_error("download failed", e, &worktree.shell_env()))
+        .and_then(|()| locate_extracted_binary(&asset.version_dir, &asset.binary_path))
+        .and_then(|()| zed::make_file_executable(&asset.binary_path))
+        .map(|()| asset.download_url.clone());
+        if !report.step("download", downloaded) {
+            return report.text;
+        }
+        asset.binary_path
+    };
+
+    let zls_path = absolute_work_path(&zls_path);
+    let zls_version = run_version_command(&zls_path, "--version");
+    report.step("zls --version", zls_version.clone());
+    if let (Some(zls), Some(zig)) = (
+        zls_version.as_deref().ok().and_then(Version::parse),
+        &zig_version,
+    ) {
+        report.step(
+            "zig compatibility",
+            match incompatibility_hint(&zls, zig) {
+                Some(hint) => Err(hint),
+                None => Ok(format!("ZLS {zls} supports Zig {zig}")),
+            },
+        );
+    }
+    report.skip(
+        "lsp handshake",
+        "the extension API cannot write to a process's stdin",
+    );
+
+    report.text
+}
+
+/// Why the settings keep ZLS from being downloaded, if they do.
+fn download_skip_reason(settings: &serde_json::Value) -> Option<String> {
+    if !settings["auto_install"].as_bool().unwrap_or(true) {
+        return Some("downloading is disabled with `\"auto_install\": false`".into());
+    }
+    deferred_download_policy(settings).map(|policy| {
+        format!("the connection is metered and `download.on_metered` is \"{policy}\"")
+    })
+}
+
+fn slash_command_output(label: &str, text: String) -> zed::SlashCommandOutput {
+    zed::SlashCommandOutput {
+        sections: vec![zed::SlashCommandOutputSection {
+            range: (0..text.len()).into(),
+            label: label.into(),
+        }],
+        text,
+    }
+}
+
+/// Locates the ZLS log file and shows its tail, or explains how to enable logging.
+/// `zig_version` is the Zig whose ZLS is preferred among the installed ones.
+fn zls_log(worktree: &zed::Worktree, zig_version: Option<&Version>) -> String {
+    let (platform, _) = zed::current_platform();
+    let binary = LspSettings::for_worktree("zls", worktree)
+        .ok()
+        .and_then(|lsp_settings| lsp_settings.binary);
+    let args = binary
+        .as_ref()
+        .and_then(|binary| binary.arguments.clone())
+        .unwrap_or_default();
+    let zls = binary
+        .and_then(|binary| binary.path)
+        .map(|path| expand_env_vars(&path, &worktree.shell_env()))
+        .or_else(|| worktree.which("zls"))
+        .or_else(|| {
+            installed_zls_binary(platform, zig_version).map(|path| absolute_work_path(&path))
+        });
+
+    let log_file = args
+        .iter()
+        .position(|arg| arg == "--log-file")
+        .and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| {
+            // `zls env` reports where this particular ZLS writes its log.
+            let output = zed::process::Command::new(zls.as_deref()?)
+                .arg("env")
+                .output()
+                .ok()?;
+            let env: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+            env["log_file"].as_str().map(String::from)
+        })
+        .or_else(|| default_zls_log_file(platform, &worktree.shell_env()));
+
+    let enable_hint = "To enable logging, pass a log file and level to ZLS and restart it:\n\n\
+        \"lsp\": { \"zls\": { \"binary\": { \"arguments\": [\"--log-file\", \"/path/to/zls.log\", \"--log-level\", \"debug\"] } } }\n";
+    let Some(log_file) = log_file else {
+        return format!("Could not determine the ZLS log file.\n\n{enable_hint}");
+    };
+
+    let tail = match platform {
+        zed::Os::Mac | zed::Os::Linux => zed::process::Command::new("tail")
+            .args(["-n", "200", &log_file])
+            .output()
+            .ok()
+            .filter(|output| output.status == Some(0)),
+        zed::Os::Windows => None,
+    };
+    match tail {
+        Some(output) => format!(
+            "ZLS log: {log_file}\n\n{}",
+            String::from_utf8_lossy(&output.stdout)
+        ),
+        None => format!("ZLS log: {log_file}\n\nThe log could not be read. {enable_hint}"),
+    }
+}
+
+/// Where ZLS writes its log when not told otherwise: `zls.log` in its cache directory.
+fn default_zls_log_file(platform: zed::Os, env: &[(String, String)]) -> Option<String> {
+    let var = |name: &str| {
+        env.iter()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| value.clone())
+    };
+    match platform {
+        zed::Os::Linux => var("XDG_CACHE_HOME")
+            .or_else(|| Some(format!("{}/.cache", var("HOME")?)))
+            .map(|cache| format!("{cache}/zls/zls.log")),
+        zed::Os::Mac => var("HOME").map(|home| format!("{home}/Library/Caches/zls/zls.log")),
+        zed::Os::Windows => var("LOCALAPPDATA").map(|data| format!("{data}\\zls\\zls.log")),
+    }
+}
+
+/// Downloads with [`DOWNLOAD_ATTEMPTS`] tries, waiting twice as long before each retry and
+/// removing whatever a failed attempt left in `version_dir`. Missing files aren't retried, and
+/// neither is anything once `timeout` has passed since the first try.
+///
+/// Zed's downloads have no timeout of their own and can't be interrupted, so a single stalled
+/// attempt still takes as long as it takes; the timeout only keeps retries from adding to it.
+/// On failure, returns the last error with the number of attempts made.
+fn download_with_retries(
+    url: &str,
+    version_dir: &str,
+    file_type: zed::DownloadedFileType,
+    timeout: Duration,
+) -> std::result::Result<(), (String, u32)> {
+    let started = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let err = match zed::download_file(url, version_dir, file_type) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        fs::remove_dir_all(version_dir).ok();
+        if is_missing_file(&err) || attempt == DOWNLOAD_ATTEMPTS {
+            return Err((err, attempt));
+        }
+        if started.elapsed() + delay >= timeout {
+            let err = format!(
+                "{err} (not retrying, `download.timeout_secs` is {}s)",
+                timeout.as_secs()
+            );
+            return Err((err, attempt));
+        }
+        eprintln!(
+            "download of {url} failed ({err}), retrying in {}s",
+            delay.as_secs()
+        );
+        std::thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Makes sure the extracted archive has the binary at `binary_path`. Should the archive put it
+/// in a folder, it's moved up from there, one level deep at most.
+fn locate_extracted_binary(version_dir: &str, binary_path: &str) -> Result<()> {
+    if fs::metadata(binary_path).is_ok_and(|stat| stat.is_file()) {
+        return Ok(());
+    }
+    let name = Path::new(binary_path)
+        .file_name()
+        .ok_or_else(|| format!("invalid ZLS binary path {binary_path}"))?;
+    let nested = fs::read_dir(version_dir)
+        .map_err(|e| format!("failed to read the extracted {version_dir}: {e}"))?
+        .flatten()
+        .map(|entry| entry.path().join(name))
+        .find(|path| fs::metadata(path).is_ok_and(|stat| stat.is_file()));
+    match nested {
+        Some(nested) => {
+            eprintln!("moving {} to {binary_path}", nested.display());
+            fs::rename(&nested, binary_path)
+                .map_err(|e| format!("failed to move {} to {binary_path}: {e}", nested.display()))
+        }
+        None => {
+            fs::remove_dir_all(version_dir).ok();
+            Err(format!(
+                "the downloaded ZLS archive has no {} in it or in a folder of it, its layout may \
+                 have changed; download ZLS yourself and set `lsp.zls.binary.path`",
+                name.to_string_lossy()
+            ))
+        }
+    }
+}
+
+/// Whether a download failed because the file doesn't exist, going by the error message.
+fn is_missing_file(err: &str) -> bool {
+    let err = err.to_lowercase();
+    err.contains("404") || err.contains("not found")
+}
+
+/// The time ZLS downloads may take including retries, from `download.timeout_secs`.
+fn download_timeout(settings: &serde_json::Value) -> Duration {
+    let secs = settings["download"]["timeout_secs"]
+        .as_u64()
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DOWNLOAD_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Downloads the asset starting with `prefix` from the GitHub release `version` of ZLS, for
+/// when [`ZLS_DOWNLOAD_BASE`] can't be reached. That only works on Windows, whose asset is a
+/// `.zip`. On failure, returns the URL that was tried.
+fn download_github_asset(
+    version: &str,
+    prefix: &str,
+    version_dir: &str,
+    timeout: Duration,
+) -> std::result::Result<(), (String, String)> {
+    let release_url = format!("https://github.com/zigtools/zls/releases/tag/{version}");
+    let release = zed::github_release_by_tag_name("zigtools/zls", version)
+        .map_err(|err| (release_url.clone(), err))?;
+    // `zls-x86-linux` must not match `zls-x86_64-linux`.
+    let assets: Vec<_> = release
+        .assets
+        .iter()
+        .filter(|asset| {
+            asset
+                .name
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with(['-', '.']))
+        })
+        .collect();
+    // Releases only have `.tar.xz` archives for macOS and Linux, which Zed can't extract.
+    let Some((asset, file_type)) = assets.iter().find_map(|asset| {
+        let file_type = downloaded_file_type(&asset.name).ok()?;
+        Some((asset, file_type))
+    }) else {
+        let error = match assets.first() {
+            Some(asset) => format!(
+                "the release has {}, but Zed can't extract that kind of archive",
+                asset.name
+            ),
+            None => format!("the release has no asset starting with {prefix}"),
+        };
+        return Err((release_url, error));
+    };
+    download_with_retries(&asset.download_url, version_dir, file_type, timeout)
+        .map_err(|(err, _)| (asset.download_url.clone(), err))
+}
+
+/// Formats a failed release lookup or download, adding what to do about it.
+///
+/// The extension API only reports errors as text, so the kind of failure is guessed from the
+/// HTTP status or wording in the message. Proxy and certificate variables from the shell
+/// environment `env` can't be applied to Zed's downloads, but are pointed out when they are
+/// likely related.
+fn network_error(context: &str, err: String, env: &[(String, String)]) -> String {
+    let message = err.to_lowercase();
+    let has_status = |status: &str| {
+        message
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word == status)
+    };
+    let set_variable = |names: &[&'static str]| {
+        names.iter().copied().find(|name| {
+            env.iter()
+                .any(|(key, value)| key.eq_ignore_ascii_case(name) && !value.is_empty())
+        })
+    };
+    let hint = if ["certificate", "tls", "ssl", "unknown issuer"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+    {
+        match set_variable(&["SSL_CERT_FILE", "SSL_CERT_DIR", "NODE_EXTRA_CA_CERTS"]) {
+            Some(variable) => format!(
+                "the TLS certificate was rejected; `{variable}` is set in your shell, which Zed \
+                 doesn't necessarily use for its own downloads, so add the certificate to the \
+                 system's trust store"
+            ),
+            None => "the TLS certificate was rejected; if a proxy intercepts TLS, add its \
+                     certificate to the system's trust store"
+                .into(),
+        }
+    } else if has_status("404") || message.contains("not found") {
+        "the release or file doesn't exist; check the ZLS version and that builds are published for this platform".into()
+    } else if has_status("403") || has_status("429") || message.contains("rate limit") {
+        "GitHub refused the request, most likely because of its API rate limit; try again later"
+            .into()
+    } else if ["500", "502", "503", "504"].into_iter().any(has_status)
+        || message.contains("service unavailable")
+        || message.contains("bad gateway")
+    {
+        "the server had a problem; try again later".into()
+    } else if [
+        "timed out",
+        "timeout",
+        "connect",
+        "dns",
+        "resolve",
+        "unreachable",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+    {
+        match set_variable(&["HTTPS_PROXY", "HTTP_PROXY", "ALL_PROXY"]) {
+            Some(variable) => format!(
+                "the server couldn't be reached; `{variable}` is set in your shell, but Zed \
+                 connects through the `proxy` from its own settings, so set that as well"
+            ),
+            None => "the server couldn't be reached; check the network connection and Zed's \
+                     `proxy` setting"
+                .into(),
+        }
+    } else {
+        return format!("{context}: {err}");
+    };
+    format!("{context}: {err} ({hint})")
+}
+
+/// The settings and the `zig` and `zls` on PATH that decide which ZLS binary is used, to notice
+/// when they change.
+fn resolution_settings(
+    settings: &serde_json::Value,
+    zig_on_path: Option<String>,
+    zls_on_path: Option<String>,
+) -> String {
+    serde_json::json!([
+        settings["toolchain"],
+        settings["zls_version"],
+        settings["zig"]["path"],
+        settings["zig"]["exe_path"],
+        settings["zls_on_path_mismatch"],
+        settings["download"],
+        settings["auto_install"],
+        // Switching to another Zig usually means another ZLS, without probing `zig version`.
+        zig_on_path,
+        // A `zls` installed on PATH, or removed from it, takes over from the downloaded one.
+        zls_on_path,
+    ])
+    .to_string()
+}
+
+/// The ZLS release to use with `zig`: the one with the same version, or else the newest one of
+/// the same `major.minor` series. Installed versions are used without asking GitHub.
+///
+/// Development builds of Zig have no matching release and get `None`.
+fn matching_zls_version(
+    platform: zed::Os,
+    arch: zed::Architecture,
+    zig: &Version,
+) -> Option<String> {
+    if zig.pre.is_some() {
+        return None;
+    }
+    (0..=zig.patch).rev().find_map(|patch| {
+        let version = format!("{}.{}.{patch}", zig.major, zig.minor);
+        let installed = zls_asset(platform, arch, &version, ZLS_DOWNLOAD_BASE)
+            .is_ok_and(|asset| is_usable_binary(&asset.binary_path));
+        (installed || zed::github_release_by_tag_name("zigtools/zls", &version).is_ok())
+            .then_some(version)
+    })
+}
+
+/// Asks zigtools which ZLS development build works with the `zig` in use, which is how ZLS
+/// master builds are matched to Zig nightlies. GitHub only has the tagged releases.
+fn master_zls_version(worktree: &zed::Worktree, settings: &serde_json::Value) -> Result<String> {
+    let zig = zig_executable(worktree, settings)
+        .ok_or("`\"zls_version\": \"master\"` needs `zig` to pick a matching ZLS build")?;
+    let zig_version = run_version_command(&zig, "version")?;
+    let url = format!(
+        "https://releases.zigtools.org/v1/zls/select-version?zig_version={}&compatibility=only-runtime",
+        zig_version.replace('+', "%2B")
+    );
+    let response = zed::http_client::HttpRequest::builder()
+        .method(zed::http_client::HttpMethod::Get)
+        .url(&url)
+        .redirect_policy(zed::http_client::RedirectPolicy::FollowAll)
+        .build()?
+        .fetch()
+        .map_err(|err| {
+            network_error(
+                "failed to look up the ZLS master build",
+                err,
+                &worktree.shell_env(),
+            )
+        })?;
+    let body: serde_json::Value = serde_json::from_slice(&response.body)
+        .map_err(|err| format!("invalid response from {url}: {err}"))?;
+    match body["version"].as_str() {
+        Some(version) => {
+            eprintln!("ZLS master build {version} matches zig {zig_version}");
+            Ok(version.to_string())
+        }
+        None => Err(format!(
+            "no ZLS build matches zig {zig_version}: {}",
+            body["message"].as_str().unwrap_or("unexpected response")
+        )),
+    }
+}
+
+/// Reads `zls_version`: `latest`, `master`, or an exact ZLS version that is downloaded without asking
+/// GitHub about it first.
+fn requested_zls_version(settings: &serde_json::Value) -> Result<Option<String>> {
+    let Some(version) = string_setting(settings, "zls_version") else {
+        return Ok(None);
+    };
+    if version != "latest" && version != "master" && Version::parse(&version).is_none() {
+        return Err(format!(
+            "invalid `zls_version` \"{version}\", expected \"latest\", \"master\" or e.g. \
+             \"0.14.0\""
+        ));
+    }
+    Ok(Some(version))
+}
+
+/// Reads `toolchain.version`, which pins both the expected Zig and the ZLS to download.
+fn pinned_toolchain_version(settings: &serde_json::Value) -> Result<Option<Version>> {
+    let Some(version) = string_setting(&settings["toolchain"], "version") else {
+        return Ok(None);
+    };
+    Version::parse(&version).map(Some).ok_or_else(|| {
+        format!("invalid `toolchain.version` \"{version}\", expected e.g. \"0.14.1\"")
+    })
+}
+
+/// Returns the `download.on_metered` policy if it forbids downloading right now.
+///
+/// The extension API doesn't report the connection type, so users flag a metered connection
+/// themselves with `download.metered`. As there is no way to prompt from an extension, `ask`
+/// defers the download just like `defer` does, and the error explains how to go ahead.
+fn deferred_download_policy(settings: &serde_json::Value) -> Option<String> {
+    let download = &settings["download"];
+    if !download["metered"].as_bool().unwrap_or(false) {
+        return None;
+    }
+    enum_setting(download, "on_metered", &["allow", "defer", "ask"]).filter(|p| p != "allow")
+}
+
+/// Returns the binary of an already downloaded ZLS in the extension work directory, if any,
+/// preferring one that matches `zig_version`.
+fn installed_zls_binary(platform: zed::Os, zig_version: Option<&Version>) -> Option<String> {
+    let installed = installed_zls_versions(platform);
+    let dir = preferred_zls_install(&installed, zig_version)?;
+    Some(format!("{dir}/{}", zls_binary_name(platform)))
+}
+
+/// The newest of the `installed` `zls-*` directories of the same release series as
+/// `zig_version`, or the newest one when none is or the Zig version isn't known.
+fn preferred_zls_install<'a>(
+    installed: &'a [String],
+    zig_version: Option<&Version>,
+) -> Option<&'a str> {
+    let matching = installed.iter().find(|dir| {
+        let version = dir.strip_prefix("zls-").and_then(Version::parse);
+        version
+            .zip(zig_version)
+            .is_some_and(|(zls, zig)| zls.same_series(zig))
+    });
+    matching.or(installed.first()).map(String::as_str)
+}
+
+fn zls_binary_name(platform: zed::Os) -> &'static str {
+    match platform {
+        zed::Os::Mac | zed::Os::Linux => "zls",
+        zed::Os::Windows => "zls.exe",
+    }
+}
+
+/// The `zls-*` directories in the work directory that contain a ZLS binary, newest first.
+fn installed_zls_versions(platform: zed::Os) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(".") else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("zls-"))
+        .filter(|dir| is_usable_binary(&format!("{dir}/{}", zls_binary_name(platform))))
+        .collect();
+    sort_zls_installs(&mut dirs);
+    dirs
+}
+
+/// Sorts `zls-*` directory names by version, newest first, and names without a version last.
+fn sort_zls_installs(dirs: &mut [String]) {
+    dirs.sort_by_cached_key(|dir| {
+        let version = dir.strip_prefix("zls-").and_then(Version::parse);
+        (std::cmp::Reverse(version), dir.clone())
+    });
+}
+
+/// The version of `binary` for the log. Managed installs are named after their version; other
+/// binaries are asked, except cached ones, which are meant to start without any probing.
+fn zls_binary_version(binary: &ZlsBinary) -> String {
+    let managed = binary
+        .path
+        .split_once('/')
+        .and_then(|(dir, _)| dir.strip_prefix("zls-"));
+    match managed {
+        Some(version) => version.to_string(),
+        None if binary.source == "the cache" => "(version not checked)".into(),
+        None => run_version_command(&binary.path, "--version")
+            .unwrap_or_else(|_| "(unknown version)".into()),
+    }
+}
+
+/// Removes everything from the work directory except the `keep` version, the newest other
+/// versions up to [`KEPT_ZLS_INSTALLS`], the extension's own files and versions used within
+/// `grace`, so rolling back or switching between a few versions doesn't download them again.
+fn prune_zls_installs(keep: &str, grace: Duration) -> Result<()> {
+    let mut names = Vec::new();
+    let entries = fs::read_dir(".").map_err(|e| format!("failed to list working directory {e}"))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("failed to load directory entry {e}"))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    for name in prunable_entries(&names, keep, grace, &read_zls_usage(), unix_time()) {
+        fs::remove_dir_all(name).ok();
+    }
+    Ok(())
+}
+
+/// The entries of the work directory [`prune_zls_installs`] removes, given when each version
+/// was last `usage`d and the current time `now`, both in seconds since the Unix epoch.
+fn prunable_entries<'a>(
+    names: &'a [String],
+    keep: &str,
+    grace: Duration,
+    usage: &serde_json::Map<String, serde_json::Value>,
+    now: u64,
+) -> Vec<&'a str> {
+    let mut versions: Vec<(Version, &str)> = names
+        .iter()
+        .filter(|name| name.as_str() != keep)
+        .filter_map(|name| Some((Version::parse(name.strip_prefix("zls-")?)?, name.as_str())))
+        .collect();
+    versions.sort_by(|a, b| b.0.cmp(&a.0));
+    let newest: Vec<&str> = versions
+        .iter()
+        .take(KEPT_ZLS_INSTALLS.saturating_sub(1))
+        .map(|(_, name)| *name)
+        .collect();
+
+    let mut prunable = Vec::new();
+    for name in names {
+        if [
+            keep,
+            SELECTED_ZLS_FILE,
+            ZLS_USAGE_FILE,
+            LATEST_RELEASE_FILE,
+            CACHED_BINARY_FILE,
+            TEST_BINARY_DIR,
+        ]
+        .contains(&name.as_str())
+            || newest.contains(&name.as_str())
+        {
+            continue;
+        }
+        let last_used = usage.get(name).and_then(serde_json::Value::as_u64);
+        if let Some(age) = last_used.map(|last_used| now.saturating_sub(last_used)) {
+            if age < grace.as_secs() {
+                eprintln!("keeping {name}, last used {}h ago", age / 3600);
+                continue;
+            }
+        }
+        prunable.push(name.as_str());
+    }
+    prunable
+}
+
+/// The latest ZLS release as looked up within [`BINARY_CACHE_TTL`] before `now`, if any.
+fn recent_latest_release(now: u64) -> Option<String> {
+    fresh_latest_release(&fs::read_to_string(LATEST_RELEASE_FILE).ok()?, now)
+}
+
+/// The version in the contents of [`LATEST_RELEASE_FILE`], unless it's older than
+/// [`BINARY_CACHE_TTL`] at `now`.
+fn fresh_latest_release(release: &str, now: u64) -> Option<String> {
+    let release: serde_json::Value = serde_json::from_str(release).ok()?;
+    let checked_at = release["checked_at"].as_u64()?;
+    let version = release["version"].as_str()?;
+    (now.saturating_sub(checked_at) < BINARY_CACHE_TTL.as_secs()).then(|| version.to_string())
+}
+
+fn record_latest_release(version: &str) {
+    let release = serde_json::json!({ "version": version, "checked_at": unix_time() });
+    if let Err(err) = fs::write(LATEST_RELEASE_FILE, release.to_string()) {
+        eprintln!("failed to remember the latest ZLS release: {err}");
+    }
+}
+
+/// Remembers that the managed ZLS at `binary_path` was just used; other paths are ignored.
+fn record_zls_use(binary_path: &str) {
+    let Some((dir, _)) = binary_path.split_once('/') else {
+        return;
+    };
+    if !dir.starts_with("zls-") {
+        return;
+    }
+    let mut usage = read_zls_usage();
+    usage.insert(dir.into(), unix_time().into());
+    if let Err(err) = fs::write(ZLS_USAGE_FILE, serde_json::Value::Object(usage).to_string()) {
+        eprintln!("failed to record the use of {dir}: {err}");
+    }
+}
+
+/// When each `zls-*` directory was last used, in seconds since the Unix epoch.
+fn read_zls_usage() -> serde_json::Map<String, serde_json::Value> {
+    fs::read_to_string(ZLS_USAGE_FILE)
+        .ok()
+        .and_then(|usage| serde_json::from_str(&usage).ok())
+        .unwrap_or_default()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Whether `path` in the work directory looks like a complete binary: a non-empty file.
+///
+/// The permissions can't be checked, the extension runs as WebAssembly without access to them.
+fn is_usable_binary(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|stat| stat.is_file() && stat.len() > 0)
+}
+
+/// The binary of the installed ZLS chosen with `/zls-use`, if it is still there.
+fn selected_zls_binary(platform: zed::Os) -> Option<String> {
+    let dir = fs::read_to_string(SELECTED_ZLS_FILE).ok()?;
+    let path = format!("{}/{}", dir.trim(), zls_binary_name(platform));
+    if is_usable_binary(&path) {
+        Some(path)
+    } else {
+        eprintln!("{path} selected with /zls-use no longer exists, ignoring the selection");
+        None
+    }
+}
+
+/// Removes every ZLS installed by the extension, along with the `/zls-use` selection and the
+/// remembered latest release, so the next start looks ZLS up and downloads it from scratch.
+///
+/// Slash commands can't reach the running extension's cache, but it's only trusted while the
+/// binary it names still exists, which after this it doesn't.
+fn zls_reinstall() -> Result<String> {
+    let entries = fs::read_dir(".").map_err(|e| format!("failed to list installed ZLS: {e}"))?;
+    let mut removed = Vec::new();
+    for name in entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("zls-"))
+    {
+        // A running ZLS can't be removed on Windows.
+        fs::remove_dir_all(&name).map_err(|e| {
+            format!("failed to remove {name}: {e}; stop the language server and try again")
+        })?;
+        removed.push(name["zls-".len()..].to_string());
+    }
+    for file in [SELECTED_ZLS_FILE, LATEST_RELEASE_FILE, CACHED_BINARY_FILE] {
+        fs::remove_file(file).ok();
+    }
+
+    let removed = if removed.is_empty() {
+        "No ZLS was installed by the extension.".to_string()
+    } else {
+        format!("Removed ZLS {}.", removed.join(", "))
+    };
+    Ok(format!(
+        "{removed} Restart the language server to download ZLS again."
+    ))
+}
+
+/// Lists the installed ZLS versions, or selects which one to use.
+///
+/// `auto` drops the selection so ZLS is looked up as usual again.
+fn zls_use(version: Option<&str>) -> Result<String> {
+    let (platform, _) = zed::current_platform();
+    let selected = fs::read_to_string(SELECTED_ZLS_FILE).ok();
+    let selected = selected.as_deref().map(str::trim);
+    let Some(version) = version else {
+        let installed = installed_zls_versions(platform);
+        if installed.is_empty() {
+            return Ok("No ZLS is installed by the extension.".into());
+        }
+        let mut text = String::from("Installed ZLS versions:\n");
+        for dir in installed {
+            let marker = if Some(dir.as_str()) == selected {
+                " (selected)"
+            } else {
+                ""
+            };
+            text.push_str(&format!("- {}{marker}\n", &dir["zls-".len()..]));
+        }
+        return Ok(text);
+    };
+
+    if version == "auto" {
+        if selected.is_some() {
+            fs::remove_file(SELECTED_ZLS_FILE)
+                .map_err(|e| format!("failed to remove the ZLS selection: {e}"))?;
+        }
+        return Ok("ZLS is looked up as usual again. Restart the language server to apply.".into());
+    }
+
+    let dir = format!("zls-{}", version.trim_start_matches("zls-"));
+    let path = format!("{dir}/{}", zls_binary_name(platform));
+    if !is_usable_binary(&path) {
+        return Err(format!(
+            "ZLS {version} is not installed; run /zls-use to list installed versions"
+        ));
+    }
+    let reported = run_version_command(&absolute_work_path(&path), "--version")
+        .map_err(|e| format!("ZLS {version} doesn't run: {e}"))?;
+    fs::write(SELECTED_ZLS_FILE, &dir)
+        .map_err(|e| format!("failed to save the ZLS selection: {e}"))?;
+    Ok(format!(
+        "Selected ZLS {reported}. Restart the language server to use it."
+    ))
+}
+
+/// Logs which ZLS was found on PATH and checks that it matches the Zig in use.
+///
+/// Returns `false` when the versions disagree and the `zls_on_path_mismatch` setting asks
+/// for the managed download to be used instead.
+fn accept_zls_on_path(
+    path: &str,
+    zig_version: Option<Version>,
+    settings: &serde_json::Value,
+) -> bool {
+    let zls_version = run_version_command(path, "--version");
+    match &zls_version {
+        Ok(version) => eprintln!("found zls {version} on PATH at {path}"),
+        Err(err) => eprintln!("found zls on PATH at {path}, but failed to get its version: {err}"),
+    }
+
+    let (Some(zls_version), Some(zig_version)) = (
+        zls_version.ok().as_deref().and_then(Version::parse),
+        zig_version,
+    ) else {
+        return true;
+    };
+    let Some(policy) = zls_on_path_mismatch(&zls_version, &zig_version, settings) else {
+        return true;
+    };
+    let download = policy == "download";
+    eprintln!(
+        "warning: zls {zls_version} at {path} does not match zig {zig_version}{}",
+        if download {
+            ", using the managed download instead"
+        } else {
+            ""
+        }
+    );
+    !download
+}
+
+/// The `zls_on_path_mismatch` policy that applies to a `zls` on PATH: `"warn"` or
+/// `"download"` when it's of another release series than Zig, `None` when they match.
+fn zls_on_path_mismatch(
+    zls_version: &Version,
+    zig_version: &Version,
+    settings: &serde_json::Value,
+) -> Option<&'static str> {
+    if zls_version.same_series(zig_version) {
+        return None;
+    }
+    match enum_setting(settings, "zls_on_path_mismatch", &["warn", "download"]).as_deref() {
+        Some("download") => Some("download"),
+        _ => Some("warn"),
+    }
+}
+
+/// Compares Zig `found` at `zig` with the version the project `pinned` in `.zig-version` or
+/// the `minimum` it requires. A mismatch fails with the `"block"` policy and is returned as a
+/// warning otherwise.
+fn project_zig_version_problem(
+    zig: &str,
+    found: &Version,
+    pinned: Option<&Version>,
+    minimum: Option<&Version>,
+    policy: Option<&str>,
+) -> Result<Option<String>> {
+    let problem = match (pinned, minimum) {
+        _ if policy == Some("off") => return Ok(None),
+        (Some(pinned), _) if pinned != found => {
+            format!("the project pins Zig {pinned} in .zig-version, but {zig} is Zig {found}")
+        }
+        (_, Some(minimum)) if found < minimum => format!(
+            "the project requires at least Zig {minimum} in build.zig.zon, but {zig} is Zig {found}"
+        ),
+        _ => return Ok(None),
+    };
+    if policy == Some("block") {
+        return Err(format!(
+            "{problem}. Install a matching Zig or set `zig_version_check` to \"warn\"."
+        ));
+    }
+    Ok(Some(problem))
+}
+
+/// Explains how to get a matching ZLS when `zls` belongs to another release than `zig`.
+///
+/// ZLS refuses to work with, or fails to initialize against, a Zig of another release series,
+/// which from the editor looks just like a broken binary.
+fn incompatibility_hint(zls: &Version, zig: &Version) -> Option<String> {
+    if zls.same_series(zig) {
+        return None;
+    }
+    let direction = if zls > zig { "newer" } else { "older" };
+    Some(format!(
+        "ZLS {zls} is {direction} than Zig {zig} and not compatible with it. Use ZLS \
+         {}.{}.x, for example by pinning both with `\"toolchain\": {{ \"version\": \"{zig}\" }}` \
+         in the `zls` settings.",
+        zig.major, zig.minor
+    ))
+}
+
+/// The `binary.path` and `binary.arguments` of the `zls` settings. The arguments apply to
+/// whichever ZLS is found, not only to a `binary.path`.
+fn binary_settings(
+    binary: Option<zed::settings::CommandSettings>,
+) -> (Option<String>, Option<Vec<String>>) {
+    binary
+        .map(|binary| (binary.path, binary.arguments))
+        .unwrap_or_default()
+}
+
+/// Resolves a path relative to the extension work directory, leaving absolute paths untouched.
+fn absolute_work_path(path: &str) -> String {
+    match std::env::current_dir() {
+        Ok(dir) if Path::new(path).is_relative() => dir.join(path).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    }
+}
+
+/// The `zig` to run: `zig.path` if it's set and works, otherwise the one on PATH.
+fn zig_executable(worktree: &zed::Worktree, settings: &serde_json::Value) -> Option<String> {
+    configured_zig_path(worktree, settings).or_else(|| worktree.which("zig"))
+}
+
+/// Reads `zig.path`, expanding environment variables, and checks it with `zig version`.
+fn configured_zig_path(worktree: &zed::Worktree, settings: &serde_json::Value) -> Option<String> {
+    // `exe_path` is accepted too, named like ZLS's `zig_exe_path`.
+    let path = string_setting(&settings["zig"], "path")
+        .or_else(|| string_setting(&settings["zig"], "exe_path"))?;
+    let path = expand_env_vars(&path, &worktree.shell_env());
+    match run_version_command(&path, "version") {
+        Ok(_) => Some(path),
+        Err(err) => {
+            eprintln!("ignoring `zig.path` \"{path}\", falling back to PATH: {err}");
+            None
+        }
+    }
+}
+
+/// Makes sure the `binary.path` from the settings points at a ZLS that can be started, so a
+/// typo is reported as such rather than as a failed spawn later on.
+///
+/// Only the extension's work directory is visible to `fs`, so paths outside of it are checked
+/// by running `zls --version` instead.
+fn check_configured_zls(path: &str) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(stat) if stat.is_file() => Ok(()),
+        Ok(_) => Err(format!(
+            "`lsp.zls.binary.path` \"{path}\" is not a file, point it at the ZLS executable"
+        )),
+        Err(_) => run_version_command(path, "--version")
+            .map(|_| ())
+            .map_err(|err| {
+                format!(
+                    "`lsp.zls.binary.path` \"{path}\" doesn't exist or isn't executable, \
+                 fix the path or remove it to let the extension find ZLS: {err}"
+                )
+            }),
+    }
+}
+
+/// Expands `$VAR`, `${VAR}` and a leading `~` using `env`. Unknown variables are left as is,
+/// with a warning.
+fn expand_env_vars(value: &str, env: &[(String, String)]) -> String {
+    let lookup = |name: &str| {
+        env.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let mut expanded = String::new();
+    let mut rest = value;
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            if let Some(home) = lookup("HOME").or_else(|| lookup("USERPROFILE")) {
+                expanded.push_str(home);
+                rest = after;
+            }
+        }
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = match after.strip_prefix('{').and_then(|b| b.split_once('}')) {
+            Some(braced) => braced,
+            None => {
+                let end = after
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(after.len());
+                after.split_at(end)
+            }
+        };
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => {
+                expanded.push_str(value);
+                rest = remainder;
+            }
+            None => {
+                if !name.is_empty() {
+                    eprintln!("warning: `${name}` in \"{value}\" is not set, leaving it as is");
+                }
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Runs `<program> <arg>` and returns the trimmed stdout, e.g. for `zig version`.
+fn run_version_command(program: &str, arg: &str) -> Result<String> {
+    let output = zed::process::Command::new(program).arg(arg).output()?;
+    if output.status != Some(0) {
+        return Err(format!(
+            "`{program} {arg}` exited with {:?}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl zed::Extension for ZigExtension {
+    fn new() -> Self {
+        let mut extension = Self {
+            cached_binary_path: None,
+            cached_binary_resolved_at: None,
+            cached_binary_settings: None,
+            unchecked_zig_version: None,
+            worktrees: HashMap::new(),
+            build_targets: HashMap::new(),
+            build_file_hashes: HashMap::new(),
+            zls_starts: HashMap::new(),
+            zig_version: None,
+        };
+        extension.restore_cached_binary();
+        extension
+    }
+
+    fn language_server_command(
+        &mut self,
+        language_server_id: &LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<zed::Command> {
+        let settings = self.zls_settings(worktree);
+        self.check_project_zig_version(worktree, &settings)?;
         let zls_binary = self.language_server_binary(language_server_id, worktree)?;
-        Ok(zed::Command {
+        let zls_version = zls_binary_version(&zls_binary);
+        eprintln!(
+            "starting ZLS {zls_version} at {} from {}",
+            absolute_work_path(&zls_binary.path),
+            zls_binary.source
+        );
+        // Only compares versions that are already known, this runs on every start.
+        if let (Some(zls), Some(zig)) = (Version::parse(&zls_version), &self.zig_version) {
+            if let Some(hint) = incompatibility_hint(&zls, zig) {
+                eprintln!(
+                    "warning: {hint} Alternatively, `/zls-reinstall` downloads ZLS for the \
+                     current Zig again."
+                );
+            }
+        }
+        self.check_crash_loop(worktree, &settings, &zls_binary.path)?;
+
+        // Running the binary once before handing it to Zed surfaces binaries that die on
+        // startup (e.g. built for another platform) with their actual error output.
+        if settings["startup_probe"].as_bool().unwrap_or(false) {
+            run_version_command(&absolute_work_path(&zls_binary.path), "--version")
+                .map_err(|err| format!("ZLS at {} failed to start: {err}", zls_binary.path))?;
+        }
+
+        let command = zed::Command {
             command: zls_binary.path,
             args: zls_binary.args.unwrap_or_default(),
             env: zls_binary.environment.unwrap_or_default(),
-        })
+        };
+        if zed::current_platform().0 == zed::Os::Windows {
+            if let Some(problem) = windows_command_line_problem(&command) {
+                eprintln!("warning: {problem}");
+            }
+        }
+        Ok(command)
     }
 
     fn language_server_workspace_configuration(
@@ -162,11 +2249,68 @@ impl zed::Extension for ZigExtension {
         _language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<Option<serde_json::Value>> {
-        let settings = LspSettings::for_worktree("zls", worktree)
-            .ok()
-            .and_then(|lsp_settings| lsp_settings.settings.clone())
-            .unwrap_or_default();
-        Ok(Some(settings))
+        let settings = self.zls_settings(worktree);
+        Ok(Some(workspace_configuration(worktree, settings)))
+    }
+
+    fn run_slash_command(
+        &self,
+        command: zed::SlashCommand,
+        args: Vec<String>,
+        worktree: Option<&zed::Worktree>,
+    ) -> Result<zed::SlashCommandOutput, String> {
+        match command.name.as_str() {
+            "zls-doctor" => {
+                let worktree = worktree.ok_or("`/zls-doctor` requires a worktree")?;
+                Ok(slash_command_output("ZLS doctor", zls_doctor(worktree)))
+            }
+            "zig-config" => {
+                let worktree = worktree.ok_or("`/zig-config` requires a worktree")?;
+                let config = self.effective_configuration(worktree);
+                let text = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+                eprintln!("zig extension configuration: {text}");
+                Ok(slash_command_output("Zig configuration", text))
+            }
+            "zls-log" => {
+                let worktree = worktree.ok_or("`/zls-log` requires a worktree")?;
+                Ok(slash_command_output(
+                    "ZLS log",
+                    zls_log(worktree, self.zig_version.as_ref()),
+                ))
+            }
+            "zls-use" => {
+                let text = zls_use(args.first().map(String::as_str))?;
+                Ok(slash_command_output("ZLS version", text))
+            }
+            "zls-reinstall" => Ok(slash_command_output("ZLS reinstall", zls_reinstall()?)),
+            command => Err(format!("unknown slash command: \"{command}\"")),
+        }
+    }
+
+    fn complete_slash_command_argument(
+        &self,
+        command: zed::SlashCommand,
+        _args: Vec<String>,
+    ) -> Result<Vec<zed::SlashCommandArgumentCompletion>, String> {
+        match command.name.as_str() {
+            "zls-use" => {
+                let (platform, _) = zed::current_platform();
+                let mut versions: Vec<String> = installed_zls_versions(platform)
+                    .into_iter()
+                    .map(|dir| dir["zls-".len()..].to_string())
+                    .collect();
+                versions.push("auto".into());
+                Ok(versions
+                    .into_iter()
+                    .map(|version| zed::SlashCommandArgumentCompletion {
+                        label: version.clone(),
+                        new_text: version,
+                        run_command: true,
+                    })
+                    .collect())
+            }
+            _ => Ok(Vec::new()),
+        }
     }
 
     fn dap_locator_create_scenario(
@@ -176,39 +2320,122 @@ impl zed::Extension for ZigExtension {
         resolved_label: String,
         debug_adapter_name: String,
     ) -> Option<zed::DebugScenario> {
-        if build_task.command != "zig" {
+        let cwd = build_task.cwd.clone();
+        let zig = self
+            .worktree_for(cwd.as_deref())
+            .map(|(_, info)| info.zig_settings.command())
+            .unwrap_or_else(|| "zig".into());
+        if build_task.command != "zig" && build_task.command != zig {
             return None;
         }
 
-        let cwd = build_task.cwd.clone();
-        let env = build_task.env.clone().into_iter().collect();
+        let debug_settings = self.debug_settings_for(cwd.as_deref());
+        let env: Vec<(String, String)> = build_task.env.clone().into_iter().collect();
 
         let mut args_it = build_task.args.iter();
-        let template = match args_it.next() {
+        let mut template = match args_it.next() {
             Some(arg) if arg == "build" => match args_it.next() {
-                Some(arg) if arg == "run" => zed::BuildTaskTemplate {
-                    label: "zig build".into(),
-                    command: "zig".into(),
-                    args: vec!["build".into()],
-                    env,
-                    cwd,
-                },
+                // Any other step may run a program too, e.g. `zig build demo`; steps that are
+                // known not to are left alone.
+                Some(arg) if !arg.starts_with('-') && !NON_RUN_STEPS.contains(&arg.as_str()) => {
+                    // Libraries have nothing to run, only their tests can be debugged, which
+                    // goes through `zig test` and the emitted test binary. `build.zig` can add
+                    // executables through helpers its text doesn't show, so `zig build --help`
+                    // has to agree that there are none.
+                    let library_root = self
+                        .worktree_for(cwd.as_deref())
+                        .filter(|(_, info)| info.has_executable == Some(false))
+                        .map(|(root, _)| root.to_string());
+                    let is_library = library_root.is_some_and(|root| {
+                        self.build_targets(&root)
+                            .is_some_and(|targets| targets.is_empty())
+                    });
+                    if is_library {
+                        return None;
+                    }
+
+                    // `zig build run-<name>` runs `zig-out/bin/<name>`, which the locator needs
+                    // to know to launch that executable rather than guessing one.
+                    // Other steps don't say what they run, but their name is a hint.
+                    let mut env = env;
+                    if let Some(name) = arg.strip_prefix("run-") {
+                        env.push((RUN_STEP_ENV.into(), name.into()));
+                    } else if arg != "run" {
+                        env.push((BUILD_STEP_ENV.into(), arg.clone()));
+                    }
+                    let mut args = vec!["build".into()];
+                    if let Some(build_file) = build_file_arg(&build_task.args) {
+                        args.extend(["--build-file".into(), build_file.to_string()]);
+                    }
+                    if let Some(prefix) = prefix_arg(&build_task.args) {
+                        args.extend(["--prefix".into(), prefix.to_string()]);
+                    }
+                    // Debug the program as it was built by the task, with its `-D` options and
+                    // `-f` flags. Without an explicit mode use the one from the settings or the
+                    // debug adapter's preferred one.
+                    let task_flags: Vec<String> = args_it
+                        .take_while(|arg| *arg != "--")
+                        .filter(|arg| arg.starts_with("-D") || arg.starts_with("-f"))
+                        .cloned()
+                        .collect();
+                    let task_optimize = task_flags.iter().any(|arg| arg.starts_with("-Doptimize="));
+                    args.extend(task_flags);
+                    let has_optimize_option = self
+                        .worktree_for(cwd.as_deref())
+                        .is_some_and(|(_, info)| info.has_optimize_option);
+                    let default_optimize = debug_settings
+                        .optimize
+                        .as_deref()
+                        .or_else(|| adapter_optimize_mode(&debug_adapter_name))
+                        .filter(|_| has_optimize_option && !task_optimize);
+                    if let Some(mode) = default_optimize {
+                        args.push(format!("-Doptimize={mode}"));
+                    }
+                    // Requesting a step by name replaces the default `install` step, so it has to
+                    // be spelled out. Zig orders the two by the dependencies declared in build.zig,
+                    // and a failing pre-launch step fails the build and with it the debug session.
+                    if let Some(step) = &debug_settings.pre_launch_task {
+                        args.extend([step.clone(), "install".into()]);
+                    }
+                    // Keep the program's arguments so the locator can hand them to the launch
+                    // request. `zig build` without `run` accepts and ignores them.
+                    if let Some(position) = build_task.args.iter().position(|arg| arg == "--") {
+                        args.extend(build_task.args[position..].iter().cloned());
+                    }
+                    let cwd = match (&debug_settings.build_dir, self.worktree_for(cwd.as_deref())) {
+                        (Some(build_dir), Some((root, _))) => Some(
+                            Path::new(root)
+                                .join(build_dir)
+                                .to_string_lossy()
+                                .into_owned(),
+                        ),
+                        _ => cwd,
+                    };
+                    zed::BuildTaskTemplate {
+                        label: "zig build".into(),
+                        command: zig,
+                        args,
+                        env,
+                        cwd,
+                    }
+                }
                 _ => return None,
             },
             Some(arg) if arg == "test" => {
-                let test_exe_path = get_test_exe_path().unwrap();
-                let mut args: Vec<String> = build_task
-                    .args
-                    .into_iter()
-                    // TODO verify if this is required on non-Windows platforms
-                    .map(|s| s.replace("\"", "'"))
-                    .collect();
-                args.push("--test-no-exec".into());
-                args.push(format!("-femit-bin={test_exe_path}"));
-
+                let (platform, _) = zed::current_platform();
+                let test_exe_path = get_test_exe_path(
+                    self.worktree_for(cwd.as_deref()).map(|(root, _)| root),
+                    platform,
+                )?;
+                let (label, args) = test_build_args(
+                    build_task.args,
+                    &test_exe_path,
+                    debug_settings.test_strip,
+                    platform,
+                );
                 zed::BuildTaskTemplate {
-                    label: "zig test --test-no-exec".into(),
-                    command: "zig".into(),
+                    label,
+                    command: zig,
                     args,
                     env,
                     cwd,
@@ -216,15 +2443,18 @@ impl zed::Extension for ZigExtension {
             }
             Some(arg) if arg == "run" => zed::BuildTaskTemplate {
                 label: "zig run".into(),
-                command: "zig".into(),
+                command: zig,
                 args: vec!["run".into()],
                 env,
                 cwd,
             },
             _ => return None,
         };
+        if let Some((_, info)) = self.worktree_for(template.cwd.as_deref()) {
+            info.zig_settings.apply(&mut template.args);
+        }
 
-        let config = serde_json::Value::Null;
+        let config = debug_settings.adapter_config(&debug_adapter_name);
         let Ok(config) = serde_json::to_string(&config) else {
             return None;
         };
@@ -246,40 +2476,131 @@ impl zed::Extension for ZigExtension {
     fn run_dap_locator(
         &mut self,
         _locator_name: String,
-        build_task: zed::TaskTemplate,
+        mut build_task: zed::TaskTemplate,
     ) -> Result<zed::DebugRequest, String> {
+        let mut take_env = |name: &str| {
+            let position = build_task.env.iter().position(|(key, _)| key == name)?;
+            Some(build_task.env.remove(position).1)
+        };
+        let run_step = take_env(RUN_STEP_ENV);
+        let build_step = take_env(BUILD_STEP_ENV);
         let mut args_it = build_task.args.iter();
         match args_it.next() {
             Some(arg) if arg == "build" => {
-                // We only handle the default case where the binary name matches the project name.
-                // This is valid for projects created with `zig init`.
-                // In other cases, the user should provide a custom debug configuration.
-                let exec = get_project_name(&build_task).ok_or("Failed to get project name")?;
-
-                let request = zed::LaunchRequest {
-                    program: format!("zig-out/bin/{exec}"),
-                    cwd: build_task.cwd,
-                    args: vec![],
-                    envs: build_task.env.into_iter().collect(),
+                // The `debug.program` setting is used as is, which allows debugging build outputs
+                // that aren't a native executable named after the project (e.g. a wrapper script).
+                let debug_settings = self.debug_settings_for(build_task.cwd.as_deref());
+                // `zig-out` is created next to the build file, which isn't necessarily in the
+                // task's directory: it's passed with `--build-file` or found in a parent.
+                let build_dir = build_file_arg(&build_task.args)
+                    .and_then(|build_file| Path::new(build_file).parent())
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .map(|dir| match &build_task.cwd {
+                        Some(cwd) => Path::new(cwd).join(dir).to_string_lossy().into_owned(),
+                        None => dir.to_string_lossy().into_owned(),
+                    })
+                    .or_else(|| {
+                        let cwd = build_task.cwd.as_deref()?;
+                        self.find_build_dir(cwd)
+                            .filter(|dir| Path::new(dir) != Path::new(cwd))
+                    });
+                // Executables are installed to `<prefix>/bin`, where the prefix defaults to
+                // `zig-out` next to the build file and is otherwise relative to the task's cwd.
+                let bin_dir = match (prefix_arg(&build_task.args), &build_task.cwd) {
+                    (Some(prefix), Some(cwd)) => {
+                        Path::new(cwd).join(prefix).to_string_lossy().into_owned() + "/bin"
+                    }
+                    (Some(prefix), None) => format!("{prefix}/bin"),
+                    (None, _) => match &build_dir {
+                        Some(dir) => format!("{dir}/zig-out/bin"),
+                        None => "zig-out/bin".into(),
+                    },
+                };
+                let program = match (debug_settings.program, run_step) {
+                    (Some(program), _) => program,
+                    (None, Some(exec)) => format!("{bin_dir}/{exec}"),
+                    (None, None) => {
+                        let dir = build_dir.clone().or(build_task.cwd.clone());
+                        let mut targets = match &dir {
+                            Some(dir) => self.build_targets(dir).unwrap_or_default(),
+                            None => Vec::new(),
+                        };
+                        // Most build scripts don't declare a step per executable, in which case
+                        // the names in the worktree's build.zig are the next best thing.
+                        if targets.is_empty() {
+                            if let Some((root, info)) = self.worktree_for(dir.as_deref()) {
+                                if dir
+                                    .as_deref()
+                                    .is_none_or(|dir| Path::new(dir) == Path::new(root))
+                                {
+                                    targets = info.executables.clone();
+                                }
+                            }
+                        }
+                        if let Some(exe_name) = &debug_settings.exe_name {
+                            if !targets.is_empty() && !targets.contains(exe_name) {
+                                return Err(format!(
+                                    "`debug.exe_name` \"{exe_name}\" is not built by build.zig, \
+                                     which builds: {}. Set `debug.program` to launch another \
+                                     executable",
+                                    targets.join(", ")
+                                ));
+                            }
+                        }
+                        // A custom step named after one of the executables likely runs it.
+                        let step = build_step.filter(|step| targets.contains(step));
+                        let wanted = debug_settings
+                            .exe_name
+                            .clone()
+                            .or(step)
+                            .or_else(|| self.project_name(&build_task));
+                        // Prefer what the build script says it produces. Failing that, we only
+                        // handle the default case where the binary name matches the project
+                        // name. This is valid for projects created with `zig init`.
+                        // In other cases, the user should provide a custom debug configuration.
+                        let exec = match (wanted, targets.as_slice()) {
+                            (Some(wanted), targets) if targets.contains(&wanted) => wanted,
+                            (_, [target]) => target.clone(),
+                            (wanted, _) => wanted.ok_or(
+                                "couldn't tell which executable the build runs; set \
+                                 `debug.exe_name` or `debug.program` in the `zls` settings",
+                            )?,
+                        };
+                        format!("{bin_dir}/{exec}")
+                    }
+                };
+                let cwd = match debug_settings.launch_cwd {
+                    Some(launch_cwd) => Some(match &build_task.cwd {
+                        Some(cwd) => Path::new(cwd)
+                            .join(launch_cwd)
+                            .to_string_lossy()
+                            .into_owned(),
+                        None => launch_cwd,
+                    }),
+                    None => build_dir.or(build_task.cwd),
+                };
+
+                let args = build_task
+                    .args
+                    .iter()
+                    .skip_while(|arg| *arg != "--")
+                    .skip(1)
+                    .cloned()
+                    .collect();
+                let request = zed::LaunchRequest {
+                    program,
+                    cwd,
+                    args,
+                    envs: build_task.env.into_iter().collect(),
                 };
 
                 Ok(zed::DebugRequest::Launch(request))
             }
             Some(arg) if arg == "test" => {
-                let program = build_task
-                    .args
-                    .iter()
-                    .find_map(|arg| {
-                        arg.strip_prefix("-femit-bin=").map(|arg| {
-                            arg.split("=")
-                                .nth(1)
-                                .ok_or("Expected binary path in -femit-bin=")
-                                .map(|path| path.trim_end_matches(".exe"))
-                        })
-                    })
-                    .ok_or("Failed to extract binary path from command args")
-                    .flatten()?
-                    .to_string();
+                let program = test_binary_arg(&build_task.args, zed::current_platform().0)
+                    .ok_or("Failed to extract binary path from command args")?;
+                // Launch exactly what was emitted, including the `.exe` on Windows.
+                check_test_binary(&program)?;
                 let request = zed::LaunchRequest {
                     program,
                     cwd: build_task.cwd,
@@ -293,19 +2614,1803 @@ impl zed::Extension for ZigExtension {
     }
 }
 
+/// Where ZLS is downloaded from: `download.mirror` or `builds.zigtools.org`. Assets are looked
+/// up by the same names on a mirror.
+fn zls_download_base(settings: &serde_json::Value) -> String {
+    string_setting(&settings["download"], "mirror").unwrap_or_else(|| ZLS_DOWNLOAD_BASE.into())
+}
+
+// Note that in github releases and on zlstools.org the tar.gz asset is not shown
+// but is available at https://builds.zigtools.org/zls-{os}-{arch}-{version}.tar.gz
+fn zls_asset(
+    platform: zed::Os,
+    arch: zed::Architecture,
+    version: &str,
+    base_url: &str,
+) -> Result<ZlsAsset> {
+    // ZLS is built for every combination Zed runs on except 32-bit macOS, for which the
+    // download would just fail with a 404.
+    if platform == zed::Os::Mac && arch == zed::Architecture::X86 {
+        return Err(
+            "ZLS doesn't publish builds for 32-bit x86 macOS. Install ZLS yourself and \
+                    point `lsp.zls.binary.path` at it."
+                .into(),
+        );
+    }
+
+    // These are all the architectures the extension API reports; riscv64 or armv7 hosts can't
+    // be told apart yet. Keep the match exhaustive so new variants have to be mapped to the
+    // names used by ZLS (`riscv64`, `arm`) here.
+    let arch: &str = match arch {
+        zed::Architecture::Aarch64 => "aarch64",
+        zed::Architecture::X86 => "x86",
+        zed::Architecture::X8664 => "x86_64",
+    };
+
+    let os: &str = match platform {
+        zed::Os::Mac => "macos",
+        zed::Os::Linux => "linux",
+        zed::Os::Windows => "windows",
+    };
+
+    let extension: &str = match platform {
+        zed::Os::Mac | zed::Os::Linux => "tar.gz",
+        zed::Os::Windows => "zip",
+    };
+
+    let github_asset_prefix = format!("zls-{arch}-{os}");
+    let asset_name: String = format!("{}-{}.{}", github_asset_prefix, version, extension);
+    let download_url = format!("{}/{}", base_url.trim_end_matches('/'), asset_name);
+
+    let version_dir = format!("zls-{}", version);
+    let binary_path = match platform {
+        zed::Os::Mac | zed::Os::Linux => format!("{version_dir}/zls"),
+        zed::Os::Windows => format!("{version_dir}/zls.exe"),
+    };
+
+    let file_type = downloaded_file_type(&asset_name)?;
+
+    Ok(ZlsAsset {
+        version: version.to_string(),
+        download_url,
+        github_asset_prefix,
+        version_dir,
+        binary_path,
+        file_type,
+    })
+}
+
+/// How `zed::download_file` extracts each archive format, by file name suffix; suffixes that
+/// end in others come first. Zed can't extract `.tar.xz`, so such assets are never chosen.
+const ARCHIVE_TYPES: [(&str, zed::DownloadedFileType); 4] = [
+    (".tar.gz", zed::DownloadedFileType::GzipTar),
+    (".tgz", zed::DownloadedFileType::GzipTar),
+    (".zip", zed::DownloadedFileType::Zip),
+    (".gz", zed::DownloadedFileType::Gzip),
+];
+
+/// How `zed::download_file` has to extract an asset, going by its file name.
+fn downloaded_file_type(asset_name: &str) -> Result<zed::DownloadedFileType> {
+    ARCHIVE_TYPES
+        .iter()
+        .find(|(suffix, _)| asset_name.ends_with(suffix))
+        .map(|&(_, file_type)| file_type)
+        .ok_or_else(|| format!("unsupported archive format of ZLS asset {asset_name}"))
+}
+
+/// The configuration sent to ZLS: the user's settings without the extension's own options,
+/// completed with detected defaults.
+fn workspace_configuration(
+    worktree: &zed::Worktree,
+    mut settings: serde_json::Value,
+) -> serde_json::Value {
+    let mut defaults = serde_json::Map::new();
+    // Point ZLS at the Zig the editor finds and its standard library, rather than letting
+    // it guess one that may belong to another Zig version.
+    if let Some(zig) = zig_executable(worktree, &settings) {
+        defaults.insert("zig_exe_path".into(), zig.into());
+    }
+    // Share the cache the CLI uses. `ZIG_LOCAL_CACHE_DIR` has no ZLS option, but reaches the
+    // builds ZLS runs through the shell environment it's started with.
+    if let Some((_, dir)) = worktree
+        .shell_env()
+        .into_iter()
+        .find(|(key, value)| key == "ZIG_GLOBAL_CACHE_DIR" && !value.is_empty())
+    {
+        defaults.insert("global_cache_path".into(), dir.into());
+    }
+    // Build-on-save reports the errors of the whole project, for which it runs the build
+    // script, so it's only turned on where there is one.
+    let has_build_file = worktree.read_text_file("build.zig").is_ok();
+    defaults.insert("enable_build_on_save".into(), has_build_file.into());
+    // Autofix edits code on save, which only those who asked for it should get. ZLS 0.14
+    // replaced `enable_autofix` with `force_autofix`, so both are sent, and a choice made with
+    // either name applies to both.
+    defaults.insert("enable_autofix".into(), false.into());
+    defaults.insert("force_autofix".into(), false.into());
+
+    for key in ["enable_build_on_save", "enable_autofix", "force_autofix"] {
+        remove_invalid_bool(&mut settings, key);
+    }
+    align_autofix_settings(&mut settings);
+    if let Some(settings) = settings.as_object_mut() {
+        for key in EXTENSION_SETTINGS {
+            settings.remove(key);
+        }
+    }
+    // Detected defaults come first, then the project's `zls.json`, then the Zed settings, so
+    // what's configured closer to the editor wins.
+    let mut config = serde_json::Value::Object(defaults);
+    if let Ok(text) = worktree.read_text_file("zls.json") {
+        match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(file) if file.is_object() => merge_json(&mut config, file),
+            Ok(_) => eprintln!("ignoring zls.json, expected a JSON object"),
+            Err(err) => eprintln!("ignoring malformed zls.json: {err}"),
+        }
+    }
+    if settings.is_object() {
+        merge_json(&mut config, settings);
+    }
+    // The library has to belong to the Zig that ZLS ends up with, which `zls.json` or the
+    // settings may replace with their own `zig_exe_path`.
+    if let Some(zig) = lib_path_zig(&config).map(String::from) {
+        match zig_env::detect(&zig) {
+            Ok(env) => {
+                // Without a `lib_dir`, the library is the parent of `std_dir`. It's split by
+                // hand, as `Path` doesn't know `\` when running as WebAssembly.
+                let lib_dir = env.lib_dir.or_else(|| {
+                    let std_dir = env.std_dir?;
+                    let (parent, _) = std_dir
+                        .trim_end_matches(['/', '\\'])
+                        .rsplit_once(['/', '\\'])?;
+                    Some(parent.to_string())
+                });
+                if let Some(lib_dir) = lib_dir {
+                    config["zig_lib_path"] = lib_dir.into();
+                }
+            }
+            Err(err) => eprintln!("failed to detect the zig environment: {err}"),
+        }
+    }
+    config
+}
+
+/// The `zig_exe_path` of the merged ZLS configuration whose standard library is to be
+/// detected, unless `zig_lib_path` is configured already.
+fn lib_path_zig(config: &serde_json::Value) -> Option<&str> {
+    if !config["zig_lib_path"].is_null() {
+        return None;
+    }
+    config["zig_exe_path"].as_str()
+}
+
+/// Replaces the values of keys that look like they hold credentials.
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let key = key.to_lowercase();
+                let is_secret = ["token", "secret", "password", "auth"]
+                    .iter()
+                    .any(|word| key.contains(word));
+                if is_secret && !value.is_null() {
+                    *value = "<redacted>".into();
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Deep-merges `overrides` into `base`: objects are merged key by key, anything else in
+/// `overrides`, including arrays, replaces what `base` has. `null` leaves `base` alone.
+fn merge_json(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (_, serde_json::Value::Null) => {}
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Copies `enable_autofix` to `force_autofix`, its name since ZLS 0.14, or the other way around
+/// when only one of them is set.
+fn align_autofix_settings(settings: &mut serde_json::Value) {
+    let Some(settings) = settings.as_object_mut() else {
+        return;
+    };
+    let (from, to) = match (
+        settings.contains_key("enable_autofix"),
+        settings.contains_key("force_autofix"),
+    ) {
+        (true, false) => ("enable_autofix", "force_autofix"),
+        (false, true) => ("force_autofix", "enable_autofix"),
+        _ => return,
+    };
+    let value = settings[from].clone();
+    settings.insert(to.into(), value);
+}
+
+/// Drops a configured boolean option of another type, so the default is used instead.
+fn remove_invalid_bool(settings: &mut serde_json::Value, key: &str) {
+    if let Some(configured) = settings.get(key).filter(|value| !value.is_boolean()) {
+        eprintln!("ignoring invalid `{key}` setting {configured}, expected a boolean");
+        if let Some(settings) = settings.as_object_mut() {
+            settings.remove(key);
+        }
+    }
+}
+
+fn adapter_optimize_mode(debug_adapter_name: &str) -> Option<&'static str> {
+    ADAPTER_OPTIMIZE_MODES
+        .iter()
+        .find(|(adapter, _)| *adapter == debug_adapter_name)
+        .map(|(_, mode)| *mode)
+}
+
+/// Reads a string setting, ignoring values of any other type.
+fn string_setting(object: &serde_json::Value, key: &str) -> Option<String> {
+    let value = object.get(key)?;
+    match value.as_str() {
+        Some(value) => Some(value.to_string()),
+        None => {
+            eprintln!("ignoring invalid `{key}` setting {value}, expected a string");
+            None
+        }
+    }
+}
+
+/// Reads a string setting that must be one of `allowed`, ignoring anything else.
+fn enum_setting(object: &serde_json::Value, key: &str, allowed: &[&str]) -> Option<String> {
+    let value = object.get(key)?;
+    match value.as_str() {
+        Some(value) if allowed.contains(&value) => Some(value.to_string()),
+        _ => {
+            eprintln!("ignoring invalid `{key}` setting {value}, expected one of {allowed:?}");
+            None
+        }
+    }
+}
+
+/// Quotes a task argument for the shell Zed runs tasks with on `platform`, if it needs quoting
+/// at all.
+///
+/// On Windows that shell may be PowerShell or `cmd`, and only double quotes work in both, so
+/// they are used unless the argument contains characters PowerShell would interpret in them,
+/// or ends in a `\` that would escape the closing quote. Those fall back to PowerShell's single
+/// quotes, where a single quote is written as `''`. Elsewhere the shell is a POSIX shell, where
+/// it is written as `'\''`. Commands spawned through `zed::Command` don't go through a shell
+/// and take arguments verbatim.
+fn quote_task_arg(arg: &str, platform: zed::Os) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./\\:=+%".contains(c));
+    if is_plain {
+        return arg.to_string();
+    }
+    match platform {
+        zed::Os::Windows if !arg.contains(['"', '$', '`']) && !arg.ends_with('\\') => {
+            format!("\"{arg}\"")
+        }
+        zed::Os::Windows => format!("'{}'", arg.replace('\'', "''")),
+        zed::Os::Mac | zed::Os::Linux => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+/// Reverses [`quote_task_arg`].
+fn unquote_task_arg(arg: &str, platform: zed::Os) -> String {
+    let windows = platform == zed::Os::Windows;
+    if windows {
+        if let Some(inner) = arg.strip_prefix('"').and_then(|arg| arg.strip_suffix('"')) {
+            return inner.to_string();
+        }
+    }
+    let Some(inner) = arg
+        .strip_prefix('\'')
+        .and_then(|arg| arg.strip_suffix('\''))
+    else {
+        return arg.to_string();
+    };
+    if windows {
+        inner.replace("''", "'")
+    } else {
+        inner.replace("'\\''", "'")
+    }
+}
+
+/// The longest command line Windows accepts, in UTF-16 units.
+const WINDOWS_MAX_COMMAND_LINE: usize = 32_767;
+
+/// Explains why spawning a command would fail on Windows when its command line is longer
+/// than `CreateProcess` allows, since there is nothing sensible to trim from it.
+///
+/// The environment isn't checked: ZLS is started without one on Windows, where Zed passes
+/// its own environment on.
+fn windows_command_line_problem(command: &zed::Command) -> Option<String> {
+    // Every argument is separated by a space and may need a pair of quotes.
+    let command_line = std::iter::once(&command.command)
+        .chain(&command.args)
+        .map(|arg| arg.encode_utf16().count() + 3)
+        .sum::<usize>();
+    (command_line > WINDOWS_MAX_COMMAND_LINE).then(|| {
+        format!(
+            "the ZLS command line is {command_line} characters long, Windows only allows \
+             {WINDOWS_MAX_COMMAND_LINE}; consider moving arguments into a ZLS config file"
+        )
+    })
+}
+
+/// The build file passed with `--build-file` in `zig build` arguments.
+fn build_file_arg(args: &[String]) -> Option<&str> {
+    let position = args.iter().position(|arg| arg == "--build-file")?;
+    args.get(position + 1).map(String::as_str)
+}
+
+/// The install prefix passed with `--prefix` or `-p` in `zig build` arguments, before any `--`.
+fn prefix_arg(args: &[String]) -> Option<&str> {
+    let options = args.split(|arg| arg == "--").next()?;
+    let position = options
+        .iter()
+        .position(|arg| arg == "--prefix" || arg == "-p")?;
+    options.get(position + 1).map(String::as_str)
+}
+
+/// The `.name` of every `addExecutable` call in a build script, where it is a string literal.
+fn parse_executable_names(build_zig: &str) -> Vec<String> {
+    build_zig
+        .split("addExecutable(")
+        .skip(1)
+        .filter_map(|call| {
+            // The options struct ends with the call, nested parentheses are rare enough there.
+            let options = call.split(");").next()?;
+            let (_, after_name) = options.split_once(".name")?;
+            let value = after_name.trim_start().strip_prefix('=')?.trim_start();
+            let name = value.strip_prefix('"')?.split('"').next()?;
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+/// Collects executable names from the `Steps:` section of `zig build --help`.
+///
+/// Zig doesn't list artifacts, but build scripts conventionally add `run-<exe>` or
+/// `install-<exe>` steps for every executable when they have more than one. A plain `run` step
+/// doesn't name its executable and is left out.
+fn parse_build_targets(help: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    let steps = help
+        .lines()
+        .skip_while(|line| line.trim_end() != "Steps:")
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty());
+    for line in steps {
+        let Some(step) = line.split_whitespace().next() else {
+            continue;
+        };
+        let target = step
+            .strip_prefix("run-")
+            .or_else(|| step.strip_prefix("install-"));
+        if let Some(target) = target {
+            if !target.is_empty() && !targets.iter().any(|t| t == target) {
+                targets.push(target.to_string());
+            }
+        }
+    }
+    targets
+}
+
+fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
 fn get_project_name(task: &zed::TaskTemplate) -> Option<String> {
     task.cwd
         .as_ref()
         .and_then(|cwd| Some(Path::new(&cwd).file_name()?.to_string_lossy().into_owned()))
 }
 
-fn get_test_exe_path() -> Option<String> {
-    let test_exe_dir = std::env::current_dir().ok()?;
-    let mut name = format!("{}_{}", ZIG_TEST_EXE_BASENAME, uuid::Uuid::new_v4());
-    if zed::current_platform().0 == zed::Os::Windows {
-        name.push_str(".exe");
+/// The label and arguments of the `zig test` command that builds the tests of the task with
+/// `task_args` into `test_exe_path` without running them, optionally without debug info.
+fn test_build_args(
+    task_args: Vec<String>,
+    test_exe_path: &str,
+    strip: bool,
+    platform: zed::Os,
+) -> (String, Vec<String>) {
+    let mut args: Vec<String> = task_args
+        .into_iter()
+        // The test name is wrapped in literal double quotes by the task template,
+        // quote it properly for the shell instead.
+        .map(|arg| {
+            let arg = arg
+                .strip_prefix('"')
+                .and_then(|arg| arg.strip_suffix('"'))
+                .unwrap_or(&arg);
+            quote_task_arg(arg, platform)
+        })
+        .collect();
+    // `--test-filter` is kept so the binary only contains the test being debugged,
+    // but without a name after it Zig would reject the command.
+    if args.last().is_some_and(|arg| arg == "--test-filter") {
+        args.pop();
+    }
+    let label = match args.iter().position(|arg| arg == "--test-filter") {
+        Some(position) => format!(
+            "zig test --test-no-exec --test-filter {}",
+            args[position + 1]
+        ),
+        None => "zig test --test-no-exec".into(),
+    };
+    if strip {
+        args.push("-fstrip".into());
     }
+    args.push("--test-no-exec".into());
+    args.push(quote_task_arg(
+        &format!("-femit-bin={test_exe_path}"),
+        platform,
+    ));
+    (label, args)
+}
+
+/// The test binary that the `-femit-bin` of [`test_build_args`] writes to.
+fn test_binary_arg(args: &[String], platform: zed::Os) -> Option<String> {
+    args.iter().find_map(|arg| {
+        unquote_task_arg(arg, platform)
+            .strip_prefix("-femit-bin=")
+            .map(String::from)
+    })
+}
+
+/// Fails with an explanation when `zig test` didn't write the test binary to `path`.
+///
+/// The build has succeeded by the time the locator runs, so compile errors are ruled out and a
+/// missing binary means Zig couldn't write to the emit path.
+fn check_test_binary(path: &str) -> Result<()> {
+    let Ok(work_dir) = std::env::current_dir() else {
+        return Ok(());
+    };
+    // Only paths inside the work directory can be checked from the extension.
+    let Ok(relative) = Path::new(path).strip_prefix(&work_dir) else {
+        return Ok(());
+    };
+    match fs::metadata(relative) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(format!(
+            "`zig test` compiled the tests but didn't write the test binary to {path}. The \
+             path was rejected, for example because {} isn't writable; look for an \
+             \"unable to\" error in the task output.",
+            work_dir.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A unique path in [`TEST_BINARY_DIR`] of the work directory for the test binary.
+///
+/// Extensions don't learn when a debug session ends, so binaries are removed once they are
+/// older than [`BINARY_CACHE_TTL`] instead, when the next one is made.
+fn get_test_exe_path(worktree_root: Option<&str>, platform: zed::Os) -> Option<String> {
+    remove_old_test_binaries();
+    fs::create_dir_all(TEST_BINARY_DIR).ok()?;
+    let test_exe_dir = std::env::current_dir().ok()?.join(TEST_BINARY_DIR);
+    let name = test_exe_name(worktree_root, platform);
     Some(test_exe_dir.join(name).to_string_lossy().into_owned())
 }
 
+/// The file name of a test binary. On Windows it ends in `.exe`, which Zig doesn't add to an
+/// explicit `-femit-bin` path and Windows needs to launch it.
+///
+/// The name includes a hash of the worktree root, telling apart the binaries of projects
+/// debugged at the same time, and a random part for sessions of the same project. The locator
+/// launches whatever `-femit-bin` names, so both always agree on the path.
+fn test_exe_name(worktree_root: Option<&str>, platform: zed::Os) -> String {
+    let mut hasher = DefaultHasher::new();
+    worktree_root.hash(&mut hasher);
+    let mut name = format!(
+        "{}_{:08x}_{}",
+        ZIG_TEST_EXE_BASENAME,
+        hasher.finish() as u32,
+        uuid::Uuid::new_v4()
+    );
+    if platform == zed::Os::Windows {
+        name.push_str(".exe");
+    }
+    name
+}
+
+fn remove_old_test_binaries() {
+    let Ok(entries) = fs::read_dir(TEST_BINARY_DIR) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_old = entry
+            .metadata()
+            .and_then(|stat| stat.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age >= BINARY_CACHE_TTL);
+        if is_old {
+            fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
 zed::register_extension!(ZigExtension);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_codelldb_options() {
+        let settings = serde_json::json!({
+            "debug": { "expressions": "python", "terminal": "external", "stop_on_entry": true }
+        });
+        let config = DebugSettings::from_settings(&settings).adapter_config(CODELLDB_ADAPTER);
+        assert_eq!(
+            config,
+            serde_json::json!({ "expressions": "python", "terminal": "external", "stopOnEntry": true })
+        );
+    }
+
+    #[test]
+    fn drops_invalid_and_unsupported_debug_options() {
+        let settings = serde_json::json!({ "debug": { "expressions": "lua", "terminal": 1 } });
+        let debug_settings = DebugSettings::from_settings(&settings);
+        assert_eq!(
+            debug_settings.adapter_config(CODELLDB_ADAPTER),
+            serde_json::Value::Null
+        );
+
+        let settings = serde_json::json!({ "debug": { "expressions": "native" } });
+        let debug_settings = DebugSettings::from_settings(&settings);
+        assert_eq!(
+            debug_settings.adapter_config(GDB_ADAPTER),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_keys() {
+        let mut base = serde_json::json!({
+            "enable_build_on_save": true,
+            "zig_exe_path": "/usr/bin/zig",
+            "inlay_hints": { "show_parameter_name": true, "show_builtin": true },
+        });
+        merge_json(
+            &mut base,
+            serde_json::json!({
+                "enable_build_on_save": false,
+                "inlay_hints": { "show_builtin": false },
+            }),
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "enable_build_on_save": false,
+                "zig_exe_path": "/usr/bin/zig",
+                "inlay_hints": { "show_parameter_name": true, "show_builtin": false },
+            })
+        );
+    }
+
+    #[test]
+    fn merges_disjoint_keys() {
+        let mut base = serde_json::json!({ "zig_exe_path": "/usr/bin/zig" });
+        merge_json(
+            &mut base,
+            serde_json::json!({ "warn_style": true, "inlay_hints": { "show_builtin": false } }),
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "zig_exe_path": "/usr/bin/zig",
+                "warn_style": true,
+                "inlay_hints": { "show_builtin": false },
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_the_base_for_null_overrides() {
+        let mut base = serde_json::json!({ "zig_exe_path": "/usr/bin/zig", "nested": { "a": 1 } });
+        merge_json(
+            &mut base,
+            serde_json::json!({ "zig_exe_path": null, "nested": { "a": null }, "new": null }),
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({ "zig_exe_path": "/usr/bin/zig", "nested": { "a": 1 }, "new": null })
+        );
+    }
+
+    #[test]
+    fn replaces_arrays_and_mismatched_types() {
+        let mut base = serde_json::json!({
+            "build_on_save_args": ["-Dfoo"],
+            "inlay_hints": { "show_builtin": true },
+        });
+        merge_json(
+            &mut base,
+            serde_json::json!({ "build_on_save_args": ["-Dbar", "-Dbaz"], "inlay_hints": false }),
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({ "build_on_save_args": ["-Dbar", "-Dbaz"], "inlay_hints": false })
+        );
+    }
+
+    #[test]
+    fn applies_autofix_to_both_names() {
+        let mut settings = serde_json::json!({ "enable_autofix": true });
+        align_autofix_settings(&mut settings);
+        assert_eq!(
+            settings,
+            serde_json::json!({ "enable_autofix": true, "force_autofix": true })
+        );
+
+        let mut settings = serde_json::json!({ "force_autofix": true });
+        align_autofix_settings(&mut settings);
+        assert_eq!(
+            settings,
+            serde_json::json!({ "enable_autofix": true, "force_autofix": true })
+        );
+
+        let mut settings = serde_json::json!({ "enable_autofix": false, "force_autofix": true });
+        align_autofix_settings(&mut settings);
+        assert_eq!(
+            settings,
+            serde_json::json!({ "enable_autofix": false, "force_autofix": true })
+        );
+    }
+
+    #[test]
+    fn keeps_the_autofix_default_without_settings() {
+        let defaults = serde_json::json!({ "enable_autofix": false, "force_autofix": false });
+        let mut config = defaults.clone();
+        let mut settings = serde_json::json!({});
+        align_autofix_settings(&mut settings);
+        merge_json(&mut config, settings);
+        assert_eq!(config, defaults);
+
+        let mut settings = serde_json::json!({ "enable_autofix": true });
+        align_autofix_settings(&mut settings);
+        merge_json(&mut config, settings);
+        assert_eq!(
+            config,
+            serde_json::json!({ "enable_autofix": true, "force_autofix": true })
+        );
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_zls_installs() {
+        let names = names(&[
+            "zls-0.11.0",
+            "zls-0.12.0",
+            "zls-0.13.0",
+            "zls-0.14.0",
+            "zls-0.15.0-dev.12+abc",
+            "zls-0.9.0",
+            SELECTED_ZLS_FILE,
+            ZLS_USAGE_FILE,
+            LATEST_RELEASE_FILE,
+            CACHED_BINARY_FILE,
+            TEST_BINARY_DIR,
+            "leftover.tar.gz",
+        ]);
+        let pruned = prunable_entries(
+            &names,
+            "zls-0.12.0",
+            Duration::ZERO,
+            &serde_json::Map::new(),
+            0,
+        );
+        // 0.12.0 is in use, 0.14.0 and the 0.15.0 build are the newest others.
+        assert_eq!(
+            pruned,
+            ["zls-0.11.0", "zls-0.13.0", "zls-0.9.0", "leftover.tar.gz"]
+        );
+    }
+
+    #[test]
+    fn keeps_zls_installs_used_within_the_grace_period() {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = 100 * DAY;
+        let names = names(&[
+            "zls-0.11.0",
+            "zls-0.12.0",
+            "zls-0.13.0",
+            "zls-0.14.0",
+            "zls-0.15.0",
+        ]);
+        let usage = serde_json::json!({
+            "zls-0.11.0": now - 2 * DAY,
+            "zls-0.12.0": now - 8 * DAY,
+        });
+        let usage = usage.as_object().unwrap();
+
+        let pruned = prunable_entries(
+            &names,
+            "zls-0.15.0",
+            Duration::from_secs(7 * DAY),
+            usage,
+            now,
+        );
+        assert_eq!(pruned, ["zls-0.12.0"]);
+
+        let pruned = prunable_entries(&names, "zls-0.15.0", Duration::ZERO, usage, now);
+        assert_eq!(pruned, ["zls-0.11.0", "zls-0.12.0"]);
+    }
+
+    fn extension_with_worktree(root: &str, info: WorktreeInfo) -> ZigExtension {
+        ZigExtension {
+            cached_binary_path: None,
+            cached_binary_resolved_at: None,
+            cached_binary_settings: None,
+            unchecked_zig_version: None,
+            worktrees: HashMap::from([(root.to_string(), info)]),
+            build_targets: HashMap::new(),
+            build_file_hashes: HashMap::new(),
+            zls_starts: HashMap::new(),
+            zig_version: None,
+        }
+    }
+
+    #[test]
+    fn debug_builds_use_the_configured_zig() {
+        let info = WorktreeInfo {
+            zig_settings: ZigSettings {
+                color: None,
+                path: Some("/opt/zig/zig".into()),
+            },
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let build_task = zed::TaskTemplate {
+            label: "zig build run".into(),
+            command: "zig".into(),
+            args: vec!["build".into(), "run".into()],
+            env: Vec::new(),
+            cwd: Some("/work/app".into()),
+        };
+        let scenario = zed::Extension::dap_locator_create_scenario(
+            &mut extension,
+            "zig-locator".into(),
+            build_task,
+            "zig build run".into(),
+            CODELLDB_ADAPTER.into(),
+        )
+        .unwrap();
+        let Some(zed::BuildTaskDefinition::Template(payload)) = scenario.build else {
+            panic!("expected a build task template");
+        };
+        assert_eq!(payload.template.command, "/opt/zig/zig");
+        assert_eq!(payload.template.args, ["build"]);
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn categorizes_missing_files() {
+        let error = network_error("failed", "status 404 Not Found".into(), &[]);
+        assert!(error.starts_with("failed: status 404 Not Found ("));
+        assert!(error.contains("doesn't exist"));
+    }
+
+    #[test]
+    fn categorizes_rate_limits() {
+        for err in [
+            "HTTP status 403 Forbidden",
+            "status code 429",
+            "API rate limit exceeded",
+        ] {
+            let error = network_error("failed", err.into(), &[]);
+            assert!(error.contains("rate limit; try again later"), "{error}");
+        }
+    }
+
+    #[test]
+    fn categorizes_server_errors() {
+        for err in ["status 502", "503 Service Unavailable", "bad gateway"] {
+            let error = network_error("failed", err.into(), &[]);
+            assert!(error.contains("the server had a problem"), "{error}");
+        }
+        // Numbers that merely contain a status code aren't one.
+        let error = network_error("failed", "read 15003 bytes".into(), &[]);
+        assert_eq!(error, "failed: read 15003 bytes");
+    }
+
+    #[test]
+    fn categorizes_certificate_errors() {
+        let error = network_error(
+            "failed",
+            "invalid peer certificate: UnknownIssuer".into(),
+            &[],
+        );
+        assert!(error.contains("if a proxy intercepts TLS"), "{error}");
+
+        let error = network_error(
+            "failed",
+            "TLS handshake failed".into(),
+            &env(&[("SSL_CERT_FILE", "/etc/ssl/corp.pem")]),
+        );
+        assert!(
+            error.contains("`SSL_CERT_FILE` is set in your shell"),
+            "{error}"
+        );
+
+        // Empty variables don't count as set.
+        let error = network_error(
+            "failed",
+            "TLS handshake failed".into(),
+            &env(&[("SSL_CERT_FILE", "")]),
+        );
+        assert!(!error.contains("SSL_CERT_FILE"), "{error}");
+    }
+
+    #[test]
+    fn categorizes_connection_errors() {
+        let error = network_error(
+            "failed",
+            "operation timed out".into(),
+            &env(&[("https_proxy", "http://proxy:3128")]),
+        );
+        assert!(
+            error.contains("`HTTPS_PROXY` is set in your shell"),
+            "{error}"
+        );
+
+        let error = network_error("failed", "failed to resolve host".into(), &[]);
+        assert!(error.contains("check the network connection"), "{error}");
+    }
+
+    #[test]
+    fn leaves_unknown_errors_alone() {
+        let error = network_error("failed to download", "unexpected EOF".into(), &[]);
+        assert_eq!(error, "failed to download: unexpected EOF");
+    }
+
+    #[test]
+    fn quotes_emit_paths_with_spaces() {
+        let windows = r"-femit-bin=C:\Users\Some Name\AppData\Local\Zed\extensions\work\zig\test-binaries\zig_test.exe";
+        let quoted = quote_task_arg(windows, zed::Os::Windows);
+        assert_eq!(quoted, format!("\"{windows}\""));
+        assert_eq!(unquote_task_arg(&quoted, zed::Os::Windows), windows);
+
+        let posix = "-femit-bin=/home/some name/.local/share/zed/extensions/work/zig/test-binaries/zig_test";
+        let quoted = quote_task_arg(posix, zed::Os::Linux);
+        assert_eq!(quoted, format!("'{posix}'"));
+        assert_eq!(unquote_task_arg(&quoted, zed::Os::Mac), posix);
+    }
+
+    #[test]
+    fn unquotes_what_was_quoted() {
+        let args = [
+            "plain",
+            "two words",
+            "it's",
+            r#"say "hi""#,
+            "$HOME and `cmd`",
+            r"C:\Some Dir\",
+            "'already quoted'",
+            "",
+        ];
+        for platform in [zed::Os::Windows, zed::Os::Linux] {
+            for arg in args {
+                let quoted = quote_task_arg(arg, platform);
+                assert_eq!(unquote_task_arg(&quoted, platform), arg, "{quoted}");
+            }
+        }
+    }
+
+    const BUILD_HELP: &str = "Usage: /usr/local/bin/zig build [steps] [options]
+
+Steps:
+  install (default)            Copy build artifacts to prefix path
+  uninstall                    Remove build artifacts from prefix path
+  run                          Run the server
+  run-server                   Run the server
+  install-server               Install the server
+  run-client                   Run the client
+  test                         Run unit tests
+
+General Options:
+  -p, --prefix [path]          Where to install files (default: zig-out)
+  run-like-option              Not a step
+";
+
+    #[test]
+    fn parses_build_targets_from_steps() {
+        assert_eq!(parse_build_targets(BUILD_HELP), ["server", "client"]);
+    }
+
+    #[test]
+    fn parses_build_targets_without_named_steps() {
+        let help = "Usage: zig build [steps] [options]\n\nSteps:\n  install (default)            \
+                    Copy build artifacts to prefix path\n  run                          Run the app\n";
+        assert!(parse_build_targets(help).is_empty());
+        assert!(parse_build_targets("error: no build.zig file found\n").is_empty());
+    }
+
+    #[test]
+    fn parses_build_targets_with_crlf() {
+        let help = BUILD_HELP.replace('\n', "\r\n");
+        assert_eq!(parse_build_targets(&help), ["server", "client"]);
+    }
+
+    #[test]
+    fn leaves_plain_args_unquoted() {
+        for platform in [zed::Os::Windows, zed::Os::Linux] {
+            for arg in [
+                "test",
+                "src/main.zig",
+                r"C:\src\main.zig",
+                "-femit-bin=out/test",
+            ] {
+                assert_eq!(quote_task_arg(arg, platform), arg);
+            }
+        }
+    }
+
+    #[test]
+    fn quotes_args_with_spaces_on_windows() {
+        assert_eq!(quote_task_arg("my test", zed::Os::Windows), "\"my test\"");
+        assert_eq!(
+            quote_task_arg(r"C:\Users\Some Name\main.zig", zed::Os::Windows),
+            r#""C:\Users\Some Name\main.zig""#
+        );
+        assert_eq!(quote_task_arg("", zed::Os::Windows), "\"\"");
+    }
+
+    #[test]
+    fn quotes_args_with_quotes_on_windows() {
+        // Double quotes, `$` and a trailing `\` can't go in double quotes.
+        assert_eq!(
+            quote_task_arg(r#"say "hi""#, zed::Os::Windows),
+            r#"'say "hi"'"#
+        );
+        assert_eq!(quote_task_arg("it's $x", zed::Os::Windows), "'it''s $x'");
+        assert_eq!(
+            quote_task_arg(r"C:\Some Dir\", zed::Os::Windows),
+            r"'C:\Some Dir\'"
+        );
+        // A single quote alone fits in double quotes.
+        assert_eq!(quote_task_arg("it's", zed::Os::Windows), "\"it's\"");
+    }
+
+    fn build_task(args: &[&str], env: &[(&str, &str)], cwd: &str) -> zed::TaskTemplate {
+        zed::TaskTemplate {
+            label: format!("zig {}", args.join(" ")),
+            command: "zig".into(),
+            args: names(args),
+            env: self::env(env),
+            cwd: Some(cwd.into()),
+        }
+    }
+
+    fn launch_request(extension: &mut ZigExtension, task: zed::TaskTemplate) -> zed::LaunchRequest {
+        let request = zed::Extension::run_dap_locator(extension, "zig-locator".into(), task);
+        match request {
+            Ok(zed::DebugRequest::Launch(request)) => request,
+            Ok(_) => panic!("expected a launch request"),
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    #[test]
+    fn launches_the_debug_program_verbatim() {
+        let settings = serde_json::json!({ "debug": { "program": "scripts/../zig-out/bin/app" } });
+        let info = WorktreeInfo {
+            debug_settings: DebugSettings::from_settings(&settings),
+            build_file_hash: Some(0),
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(&["build", "--", "--verbose"], &[], "/work/app");
+        let request = launch_request(&mut extension, task);
+        assert_eq!(request.program, "scripts/../zig-out/bin/app");
+        assert_eq!(request.args, ["--verbose"]);
+        assert_eq!(request.cwd.as_deref(), Some("/work/app"));
+    }
+
+    #[test]
+    fn accepts_zls_on_path_of_the_same_series() {
+        let zls = Version::parse("0.14.1").unwrap();
+        let zig = Version::parse("0.14.0").unwrap();
+        let settings = serde_json::json!({ "zls_on_path_mismatch": "download" });
+        assert_eq!(zls_on_path_mismatch(&zls, &zig, &settings), None);
+    }
+
+    #[test]
+    fn applies_the_zls_on_path_mismatch_policy() {
+        let zls = Version::parse("0.13.0").unwrap();
+        let zig = Version::parse("0.14.0").unwrap();
+        for (settings, policy) in [
+            (serde_json::json!({}), "warn"),
+            (
+                serde_json::json!({ "zls_on_path_mismatch": "warn" }),
+                "warn",
+            ),
+            (
+                serde_json::json!({ "zls_on_path_mismatch": "download" }),
+                "download",
+            ),
+            // Unknown values fall back to the default.
+            (
+                serde_json::json!({ "zls_on_path_mismatch": "ignore" }),
+                "warn",
+            ),
+        ] {
+            assert_eq!(
+                zls_on_path_mismatch(&zls, &zig, &settings),
+                Some(policy),
+                "{settings}"
+            );
+        }
+    }
+
+    fn scenario_template(
+        extension: &mut ZigExtension,
+        task: zed::TaskTemplate,
+        adapter: &str,
+    ) -> zed::BuildTaskTemplate {
+        let label = task.label.clone();
+        let scenario = zed::Extension::dap_locator_create_scenario(
+            extension,
+            "zig-locator".into(),
+            task,
+            label,
+            adapter.into(),
+        )
+        .expect("expected a debug scenario");
+        let Some(zed::BuildTaskDefinition::Template(payload)) = scenario.build else {
+            panic!("expected a build task template");
+        };
+        payload.template
+    }
+
+    #[test]
+    fn builds_the_pre_launch_task_with_the_program() {
+        let settings = serde_json::json!({ "debug": { "pre_launch_task": "codegen" } });
+        let info = WorktreeInfo {
+            debug_settings: DebugSettings::from_settings(&settings),
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(&["build", "run", "--", "input.txt"], &[], "/work/app");
+        let template = scenario_template(&mut extension, task, GDB_ADAPTER);
+        assert_eq!(
+            template.args,
+            ["build", "codegen", "install", "--", "input.txt"]
+        );
+    }
+
+    #[test]
+    fn keeps_the_optimize_mode_of_the_run_task() {
+        let tasks: serde_json::Value =
+            serde_json::from_str(include_str!("../languages/zig/tasks.json")).unwrap();
+        let task = tasks
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|task| task["label"] == "zig build run -Doptimize=$ZIG_OPTIMIZE")
+            .expect("expected the optimize mode run task");
+        let args: Vec<&str> = task["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|arg| arg.as_str().unwrap())
+            .collect();
+        assert_eq!(args, ["build", "run", "-Doptimize=$ZIG_OPTIMIZE"]);
+
+        let info = WorktreeInfo {
+            has_optimize_option: true,
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(&args, &[("ZIG_OPTIMIZE", "ReleaseFast")], "/work/app");
+        let template = scenario_template(&mut extension, task, CODELLDB_ADAPTER);
+        // The shell expands the variable when the build runs, instead of CodeLLDB's `Debug`.
+        assert_eq!(template.args, ["build", "-Doptimize=$ZIG_OPTIMIZE"]);
+        assert_eq!(template.env, env(&[("ZIG_OPTIMIZE", "ReleaseFast")]));
+    }
+
+    #[test]
+    fn reports_command_lines_over_the_windows_limit() {
+        let mut command = zed::Command {
+            command: r"C:\tools\zls.exe".into(),
+            args: vec!["--enable-stderr-logs".into()],
+            env: Vec::new(),
+        };
+        assert_eq!(windows_command_line_problem(&command), None);
+
+        command.args.push("x".repeat(WINDOWS_MAX_COMMAND_LINE));
+        let problem = windows_command_line_problem(&command).unwrap();
+        assert!(problem.contains("consider moving arguments"), "{problem}");
+    }
+
+    #[test]
+    fn builds_debug_sessions_in_the_adapter_optimize_mode() {
+        assert_eq!(adapter_optimize_mode(CODELLDB_ADAPTER), Some("Debug"));
+        assert_eq!(adapter_optimize_mode(GDB_ADAPTER), Some("Debug"));
+        assert_eq!(adapter_optimize_mode("Delve"), None);
+
+        let info = WorktreeInfo {
+            has_optimize_option: true,
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        for (adapter, args) in [
+            (CODELLDB_ADAPTER, &["build", "-Doptimize=Debug"][..]),
+            (GDB_ADAPTER, &["build", "-Doptimize=Debug"]),
+            ("Delve", &["build"]),
+        ] {
+            let task = build_task(&["build", "run"], &[], "/work/app");
+            let template = scenario_template(&mut extension, task, adapter);
+            assert_eq!(template.args, args, "{adapter}");
+        }
+    }
+
+    #[test]
+    fn prefers_the_debug_optimize_setting() {
+        let settings = serde_json::json!({ "debug": { "optimize": "ReleaseSafe" } });
+        let info = WorktreeInfo {
+            debug_settings: DebugSettings::from_settings(&settings),
+            has_optimize_option: true,
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        for adapter in [CODELLDB_ADAPTER, "Delve"] {
+            let task = build_task(&["build", "run"], &[], "/work/app");
+            let template = scenario_template(&mut extension, task, adapter);
+            assert_eq!(
+                template.args,
+                ["build", "-Doptimize=ReleaseSafe"],
+                "{adapter}"
+            );
+        }
+
+        // Without `standardOptimizeOption` in build.zig, `zig build` would reject the option.
+        let info = WorktreeInfo {
+            debug_settings: DebugSettings::from_settings(&settings),
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(&["build", "run"], &[], "/work/app");
+        let template = scenario_template(&mut extension, task, CODELLDB_ADAPTER);
+        assert_eq!(template.args, ["build"]);
+    }
+
+    #[test]
+    fn extracts_assets_by_their_suffix() {
+        for (name, file_type) in [
+            (
+                "zls-x86_64-linux-0.14.0.tar.gz",
+                zed::DownloadedFileType::GzipTar,
+            ),
+            (
+                "zls-x86_64-linux-0.14.0.tgz",
+                zed::DownloadedFileType::GzipTar,
+            ),
+            (
+                "zls-x86_64-windows-0.14.0.zip",
+                zed::DownloadedFileType::Zip,
+            ),
+            ("zls.gz", zed::DownloadedFileType::Gzip),
+        ] {
+            assert_eq!(downloaded_file_type(name), Ok(file_type), "{name}");
+        }
+        for name in ["zls-x86_64-linux-0.14.0.tar.xz", "zls-x86_64-linux-0.14.0"] {
+            let err = downloaded_file_type(name).unwrap_err();
+            assert!(err.contains("unsupported archive format"), "{err}");
+        }
+    }
+
+    #[test]
+    fn reaches_every_archive_type() {
+        // A shorter suffix listed first, like `.gz` before `.tar.gz`, would shadow the longer one.
+        for &(suffix, file_type) in &ARCHIVE_TYPES {
+            assert_eq!(
+                downloaded_file_type(&format!("zls{suffix}")),
+                Ok(file_type),
+                "{suffix}"
+            );
+        }
+    }
+
+    #[test]
+    fn compares_the_zig_version_with_the_project() {
+        let version = |version: &str| Version::parse(version).unwrap();
+        let found = version("0.14.0");
+        let check = |pinned: Option<&str>, minimum: Option<&str>| {
+            let (pinned, minimum) = (pinned.map(version), minimum.map(version));
+            project_zig_version_problem("zig", &found, pinned.as_ref(), minimum.as_ref(), None)
+                .unwrap()
+        };
+        assert_eq!(check(Some("0.14.0"), None), None);
+        assert_eq!(check(None, Some("0.13.0")), None);
+        assert_eq!(check(None, Some("0.14.0")), None);
+        let warning = check(Some("0.13.0"), None).unwrap();
+        assert!(warning.contains("pins Zig 0.13.0"), "{warning}");
+        let warning = check(None, Some("0.15.0-dev.1+abc")).unwrap();
+        assert!(warning.contains("at least Zig 0.15.0-dev.1 "), "{warning}");
+        // A matching pin doesn't excuse a Zig older than the minimum.
+        let warning = check(Some("0.14.0"), Some("0.15.0")).unwrap();
+        assert!(warning.contains("at least Zig 0.15.0"), "{warning}");
+    }
+
+    #[test]
+    fn applies_the_zig_version_check_policy() {
+        let found = Version::parse("0.14.0").unwrap();
+        let pinned = Version::parse("0.13.0").unwrap();
+        let check = |policy| {
+            project_zig_version_problem("/usr/bin/zig", &found, Some(&pinned), None, policy)
+        };
+        assert!(check(None).unwrap().is_some());
+        assert!(check(Some("warn")).unwrap().is_some());
+        assert_eq!(check(Some("off")), Ok(None));
+        let err = check(Some("block")).unwrap_err();
+        assert!(err.contains("/usr/bin/zig is Zig 0.14.0"), "{err}");
+        assert!(err.contains("set `zig_version_check` to \"warn\""), "{err}");
+    }
+
+    #[test]
+    fn names_test_binaries_per_platform() {
+        let windows = test_exe_name(Some(r"C:\work\app"), zed::Os::Windows);
+        assert!(windows.starts_with(ZIG_TEST_EXE_BASENAME), "{windows}");
+        assert!(windows.ends_with(".exe"), "{windows}");
+        for platform in [zed::Os::Mac, zed::Os::Linux] {
+            let name = test_exe_name(Some("/work/app"), platform);
+            assert!(!name.contains('.'), "{name}");
+        }
+    }
+
+    #[test]
+    fn tells_test_binaries_of_projects_and_sessions_apart() {
+        let app = test_exe_name(Some("/work/app"), zed::Os::Linux);
+        let lib = test_exe_name(Some("/work/lib"), zed::Os::Linux);
+        let hash = |name: &str| name.split('_').nth(2).unwrap().to_string();
+        assert_ne!(hash(&app), hash(&lib));
+
+        let again = test_exe_name(Some("/work/app"), zed::Os::Linux);
+        assert_eq!(hash(&app), hash(&again));
+        assert_ne!(app, again);
+    }
+
+    #[test]
+    fn strips_test_binaries_when_asked() {
+        let task_args = names(&["test", "src/main.zig", "--test-filter", "\"parses\""]);
+        let (label, args) =
+            test_build_args(task_args.clone(), "/tmp/zig_test", false, zed::Os::Linux);
+        assert_eq!(label, "zig test --test-no-exec --test-filter parses");
+        assert_eq!(
+            args,
+            [
+                "test",
+                "src/main.zig",
+                "--test-filter",
+                "parses",
+                "--test-no-exec",
+                "-femit-bin=/tmp/zig_test"
+            ]
+        );
+
+        let (_, args) = test_build_args(task_args, "/tmp/zig_test", true, zed::Os::Linux);
+        assert_eq!(
+            args,
+            [
+                "test",
+                "src/main.zig",
+                "--test-filter",
+                "parses",
+                "-fstrip",
+                "--test-no-exec",
+                "-femit-bin=/tmp/zig_test"
+            ]
+        );
+    }
+
+    #[test]
+    fn debugs_only_the_tests_of_libraries() {
+        let library = || {
+            let info = WorktreeInfo {
+                build_file_hash: Some(0),
+                has_executable: Some(false),
+                ..WorktreeInfo::default()
+            };
+            extension_with_worktree("/work/lib", info)
+        };
+        let mut extension = library();
+        // What `zig build --help` would list, so the test doesn't run Zig.
+        extension
+            .build_targets
+            .insert("/work/lib".into(), (None, Some(Vec::new())));
+        let task = build_task(&["build", "run"], &[], "/work/lib");
+        let scenario = zed::Extension::dap_locator_create_scenario(
+            &mut extension,
+            "zig-locator".into(),
+            task,
+            "zig build run".into(),
+            CODELLDB_ADAPTER.into(),
+        );
+        assert!(scenario.is_none());
+
+        // The test binary comes from `-femit-bin` alone, there's no `zig-out/bin` to look in.
+        for platform in [zed::Os::Linux, zed::Os::Windows] {
+            let test_exe_path = r"/work/some dir/zig_test.exe";
+            let task_args = names(&["test", "src/root.zig"]);
+            let (_, args) = test_build_args(task_args, test_exe_path, false, platform);
+            assert_eq!(
+                test_binary_arg(&args, platform).as_deref(),
+                Some(test_exe_path)
+            );
+        }
+    }
+
+    #[test]
+    fn debugs_executables_zig_build_knows_about() {
+        let info = WorktreeInfo {
+            build_file_hash: Some(0),
+            has_executable: Some(false),
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        // The executable is added by a helper in another file, but it has a step.
+        extension
+            .build_targets
+            .insert("/work/app".into(), (None, Some(names(&["server"]))));
+        let task = build_task(&["build", "run-server"], &[], "/work/app");
+        let template = scenario_template(&mut extension, task, CODELLDB_ADAPTER);
+        assert_eq!(template.env, env(&[(RUN_STEP_ENV, "server")]));
+    }
+
+    #[test]
+    fn adds_the_color_option_before_program_arguments() {
+        let settings = serde_json::json!({ "zig": { "color": "off" } });
+        let zig_settings = ZigSettings::from_settings(&settings);
+
+        let mut args = names(&["build", "run"]);
+        zig_settings.apply(&mut args);
+        assert_eq!(args, ["build", "run", "--color", "off"]);
+
+        let mut args = names(&["build", "run", "--", "--color", "on"]);
+        zig_settings.apply(&mut args);
+        assert_eq!(
+            args,
+            ["build", "run", "--color", "off", "--", "--color", "on"]
+        );
+
+        // The command's own choice is kept.
+        let mut args = names(&["build", "--color", "on", "run"]);
+        zig_settings.apply(&mut args);
+        assert_eq!(args, ["build", "--color", "on", "run"]);
+
+        let mut args = names(&["build", "run"]);
+        ZigSettings::default().apply(&mut args);
+        assert_eq!(args, ["build", "run"]);
+    }
+
+    #[test]
+    fn reuses_a_fresh_cached_binary() {
+        let mut extension = extension_with_worktree("/work/app", WorktreeInfo::default());
+        extension.cached_binary_path = Some("/usr/bin/zls".into());
+        extension.cached_binary_resolved_at = Some(Instant::now());
+        // Returned before anything is probed or downloaded.
+        assert_eq!(
+            extension
+                .fresh_cached_binary(Some("/usr/bin/zls"))
+                .as_deref(),
+            Some("/usr/bin/zls")
+        );
+        // Gone from PATH, and outside the work directory where it could be found.
+        assert_eq!(extension.fresh_cached_binary(None), None);
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        extension.cached_binary_resolved_at = Instant::now().checked_sub(day);
+        if extension.cached_binary_resolved_at.is_some() {
+            assert_eq!(extension.fresh_cached_binary(Some("/usr/bin/zls")), None);
+        }
+    }
+
+    #[test]
+    fn notices_changes_that_resolve_zls_again() {
+        let settings = serde_json::json!({ "zls_version": "0.14.0", "enable_snippets": true });
+        let zig = Some("/usr/bin/zig".to_string());
+        let snapshot = resolution_settings(&settings, zig.clone(), None);
+        assert_eq!(resolution_settings(&settings, zig.clone(), None), snapshot);
+
+        // ZLS options don't decide which binary is used.
+        let other = serde_json::json!({ "zls_version": "0.14.0", "enable_snippets": false });
+        assert_eq!(resolution_settings(&other, zig.clone(), None), snapshot);
+
+        let other = serde_json::json!({ "zls_version": "0.13.0", "enable_snippets": true });
+        assert_ne!(resolution_settings(&other, zig.clone(), None), snapshot);
+        let other = serde_json::json!({
+            "zls_version": "0.14.0",
+            "enable_snippets": true,
+            "download": { "mirror": "https://mirror.example" },
+        });
+        assert_ne!(resolution_settings(&other, zig.clone(), None), snapshot);
+        let zls = Some("/usr/local/bin/zls".to_string());
+        assert_ne!(resolution_settings(&settings, zig, zls), snapshot);
+        assert_ne!(
+            resolution_settings(&settings, Some("/opt/zig/zig".into()), None),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn sorts_zls_installs_newest_first() {
+        let mut dirs = names(&[
+            "zls-0.9.0",
+            "zls-0.14.0",
+            "zls-broken",
+            "zls-0.15.0-dev.9+abc",
+            "zls-0.15.0-dev.10+def",
+            "zls-0.13.0",
+        ]);
+        sort_zls_installs(&mut dirs);
+        assert_eq!(
+            dirs,
+            [
+                "zls-0.15.0-dev.10+def",
+                "zls-0.15.0-dev.9+abc",
+                "zls-0.14.0",
+                "zls-0.13.0",
+                "zls-0.9.0",
+                "zls-broken",
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_the_install_matching_zig() {
+        let dirs = names(&["zls-0.15.0", "zls-0.14.1", "zls-0.14.0", "zls-0.13.0"]);
+        let zig = Version::parse("0.14.0").unwrap();
+        assert_eq!(preferred_zls_install(&dirs, Some(&zig)), Some("zls-0.14.1"));
+        let zig = Version::parse("0.16.0-dev.1+abc").unwrap();
+        assert_eq!(preferred_zls_install(&dirs, Some(&zig)), Some("zls-0.15.0"));
+        assert_eq!(preferred_zls_install(&dirs, None), Some("zls-0.15.0"));
+        assert_eq!(preferred_zls_install(&[], None), None);
+    }
+
+    #[test]
+    fn explains_incompatible_zls_versions() {
+        let zig = Version::parse("0.14.0").unwrap();
+        let hint = incompatibility_hint(&Version::parse("0.15.1").unwrap(), &zig).unwrap();
+        assert!(
+            hint.starts_with("ZLS 0.15.1 is newer than Zig 0.14.0"),
+            "{hint}"
+        );
+        assert!(hint.contains("Use ZLS 0.14.x"), "{hint}");
+        assert!(
+            hint.contains(r#"`"toolchain": { "version": "0.14.0" }`"#),
+            "{hint}"
+        );
+
+        let hint = incompatibility_hint(&Version::parse("0.13.0").unwrap(), &zig).unwrap();
+        assert!(
+            hint.starts_with("ZLS 0.13.0 is older than Zig 0.14.0"),
+            "{hint}"
+        );
+
+        for zls in ["0.14.0", "0.14.1", "0.14.0-dev.5+abc"] {
+            let zls = Version::parse(zls).unwrap();
+            assert_eq!(incompatibility_hint(&zls, &zig), None, "{zls}");
+        }
+    }
+
+    #[test]
+    fn runs_in_a_pseudo_terminal_with_pty() {
+        let config = |debug: serde_json::Value, adapter| {
+            let settings = serde_json::json!({ "debug": debug });
+            DebugSettings::from_settings(&settings).adapter_config(adapter)
+        };
+        assert_eq!(
+            config(serde_json::json!({ "pty": true }), CODELLDB_ADAPTER),
+            serde_json::json!({ "terminal": "integrated" })
+        );
+        // An explicit terminal wins.
+        assert_eq!(
+            config(
+                serde_json::json!({ "pty": true, "terminal": "external" }),
+                CODELLDB_ADAPTER
+            ),
+            serde_json::json!({ "terminal": "external" })
+        );
+        assert_eq!(
+            config(serde_json::json!({ "pty": false }), CODELLDB_ADAPTER),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            config(serde_json::json!({ "pty": true }), GDB_ADAPTER),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn names_zls_assets_for_every_architecture() {
+        for (platform, arch, name) in [
+            (
+                zed::Os::Linux,
+                zed::Architecture::Aarch64,
+                "zls-aarch64-linux-0.14.0.tar.gz",
+            ),
+            (
+                zed::Os::Linux,
+                zed::Architecture::X86,
+                "zls-x86-linux-0.14.0.tar.gz",
+            ),
+            (
+                zed::Os::Linux,
+                zed::Architecture::X8664,
+                "zls-x86_64-linux-0.14.0.tar.gz",
+            ),
+            (
+                zed::Os::Mac,
+                zed::Architecture::Aarch64,
+                "zls-aarch64-macos-0.14.0.tar.gz",
+            ),
+            (
+                zed::Os::Mac,
+                zed::Architecture::X8664,
+                "zls-x86_64-macos-0.14.0.tar.gz",
+            ),
+            (
+                zed::Os::Windows,
+                zed::Architecture::Aarch64,
+                "zls-aarch64-windows-0.14.0.zip",
+            ),
+            (
+                zed::Os::Windows,
+                zed::Architecture::X86,
+                "zls-x86-windows-0.14.0.zip",
+            ),
+            (
+                zed::Os::Windows,
+                zed::Architecture::X8664,
+                "zls-x86_64-windows-0.14.0.zip",
+            ),
+        ] {
+            let asset = zls_asset(platform, arch, "0.14.0", ZLS_DOWNLOAD_BASE).unwrap();
+            assert_eq!(asset.download_url, format!("{ZLS_DOWNLOAD_BASE}/{name}"));
+            assert!(name.starts_with(&asset.github_asset_prefix), "{name}");
+        }
+
+        let err = zls_asset(
+            zed::Os::Mac,
+            zed::Architecture::X86,
+            "0.14.0",
+            ZLS_DOWNLOAD_BASE,
+        )
+        .err()
+        .unwrap();
+        assert!(err.contains("32-bit x86 macOS"), "{err}");
+    }
+
+    #[test]
+    fn downloads_pinned_versions_for_each_platform() {
+        let settings = serde_json::json!({
+            "toolchain": { "version": "0.13" },
+            "download": { "mirror": "https://mirror.example/zls/" },
+        });
+        let version = pinned_toolchain_version(&settings)
+            .unwrap()
+            .unwrap()
+            .to_string();
+        for (platform, url, binary_path) in [
+            (
+                zed::Os::Linux,
+                "https://mirror.example/zls/zls-x86_64-linux-0.13.0.tar.gz",
+                "zls-0.13.0/zls",
+            ),
+            (
+                zed::Os::Mac,
+                "https://mirror.example/zls/zls-x86_64-macos-0.13.0.tar.gz",
+                "zls-0.13.0/zls",
+            ),
+            (
+                zed::Os::Windows,
+                "https://mirror.example/zls/zls-x86_64-windows-0.13.0.zip",
+                "zls-0.13.0/zls.exe",
+            ),
+        ] {
+            let asset = zls_asset(
+                platform,
+                zed::Architecture::X8664,
+                &version,
+                &zls_download_base(&settings),
+            )
+            .unwrap();
+            assert_eq!(asset.download_url, url);
+            assert_eq!(asset.version_dir, "zls-0.13.0");
+            assert_eq!(asset.binary_path, binary_path);
+        }
+    }
+
+    #[test]
+    fn finds_the_build_file_of_nested_projects() {
+        // A repository without a build.zig of its own, with a project in `app`.
+        let mut extension = extension_with_worktree("/work/repo", WorktreeInfo::default());
+        // What `zig build --help` would say in each directory, so the test doesn't run Zig.
+        extension
+            .build_targets
+            .insert("/work/repo/app/src".into(), (None, None));
+        extension
+            .build_targets
+            .insert("/work/repo/app".into(), (None, Some(Vec::new())));
+        extension
+            .build_targets
+            .insert("/work/repo/docs".into(), (None, None));
+
+        assert_eq!(
+            extension.find_build_dir("/work/repo/app/src").as_deref(),
+            Some("/work/repo/app")
+        );
+        assert_eq!(
+            extension.find_build_dir("/work/repo/app").as_deref(),
+            Some("/work/repo/app")
+        );
+        assert_eq!(extension.find_build_dir("/work/repo/docs"), None);
+        assert_eq!(extension.find_build_dir("/work/repo"), None);
+
+        let task = build_task(&["build"], &[(RUN_STEP_ENV, "app")], "/work/repo/app/src");
+        let request = launch_request(&mut extension, task);
+        assert_eq!(request.program, "/work/repo/app/zig-out/bin/app");
+        assert_eq!(request.cwd.as_deref(), Some("/work/repo/app"));
+    }
+
+    #[test]
+    fn reuses_the_latest_release_within_a_day() {
+        const HOUR: u64 = 60 * 60;
+        let now = 1000 * HOUR;
+        let release = serde_json::json!({ "version": "0.14.0", "checked_at": now - 23 * HOUR });
+        // A fresh entry is used instead of asking GitHub.
+        assert_eq!(
+            fresh_latest_release(&release.to_string(), now).as_deref(),
+            Some("0.14.0")
+        );
+
+        let release = serde_json::json!({ "version": "0.14.0", "checked_at": now - 24 * HOUR });
+        assert_eq!(fresh_latest_release(&release.to_string(), now), None);
+        assert_eq!(fresh_latest_release(r#"{"version": "0.14.0"}"#, now), None);
+        assert_eq!(fresh_latest_release("", now), None);
+    }
+
+    #[test]
+    fn skips_downloads_the_settings_rule_out() {
+        assert_eq!(download_skip_reason(&serde_json::json!({})), None);
+        let settings = serde_json::json!({ "auto_install": false });
+        let reason = download_skip_reason(&settings).unwrap();
+        assert!(reason.contains("auto_install"), "{reason}");
+
+        let settings =
+            serde_json::json!({ "download": { "metered": true, "on_metered": "defer" } });
+        let reason = download_skip_reason(&settings).unwrap();
+        assert!(reason.contains("\"defer\""), "{reason}");
+        let settings =
+            serde_json::json!({ "download": { "metered": true, "on_metered": "allow" } });
+        assert_eq!(download_skip_reason(&settings), None);
+    }
+
+    #[test]
+    fn passes_arguments_without_a_binary_path() {
+        let binary = zed::settings::CommandSettings {
+            path: None,
+            arguments: Some(names(&["--enable-stderr-logs"])),
+            env: None,
+        };
+        let (path, args) = binary_settings(Some(binary));
+        assert_eq!(path, None);
+        assert_eq!(args, Some(names(&["--enable-stderr-logs"])));
+
+        assert_eq!(binary_settings(None), (None, None));
+    }
+
+    #[test]
+    fn keeps_the_optimize_mode_of_the_task() {
+        let settings = serde_json::json!({ "debug": { "optimize": "ReleaseFast" } });
+        let info = WorktreeInfo {
+            debug_settings: DebugSettings::from_settings(&settings),
+            has_optimize_option: true,
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(
+            &[
+                "build",
+                "run",
+                "-Doptimize=Debug",
+                "-Dtarget=native",
+                "--",
+                "-Dkept",
+            ],
+            &[],
+            "/work/app",
+        );
+        let template = scenario_template(&mut extension, task, CODELLDB_ADAPTER);
+        assert_eq!(
+            template.args,
+            [
+                "build",
+                "-Doptimize=Debug",
+                "-Dtarget=native",
+                "--",
+                "-Dkept"
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_the_library_of_the_configured_zig() {
+        let mut config = serde_json::json!({ "zig_exe_path": "/usr/bin/zig" });
+        merge_json(
+            &mut config,
+            serde_json::json!({ "zig_exe_path": "/opt/zig-0.15/zig" }),
+        );
+        assert_eq!(lib_path_zig(&config), Some("/opt/zig-0.15/zig"));
+
+        let config = serde_json::json!({
+            "zig_exe_path": "/opt/zig-0.15/zig",
+            "zig_lib_path": "/opt/zig-0.15/lib",
+        });
+        assert_eq!(lib_path_zig(&config), None);
+        assert_eq!(lib_path_zig(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn launches_from_the_install_prefix() {
+        let info = WorktreeInfo {
+            build_file_hash: Some(0),
+            ..WorktreeInfo::default()
+        };
+        let mut extension = extension_with_worktree("/work/app", info);
+        let task = build_task(
+            &["build", "run-server", "--prefix", "build/out"],
+            &[],
+            "/work/app",
+        );
+        let template = scenario_template(&mut extension, task, CODELLDB_ADAPTER);
+        assert_eq!(template.args, ["build", "--prefix", "build/out"]);
+
+        let task = zed::TaskTemplate {
+            label: template.label,
+            command: template.command,
+            args: template.args,
+            env: template.env,
+            cwd: template.cwd,
+        };
+        let request = launch_request(&mut extension, task);
+        assert_eq!(request.program, "/work/app/build/out/bin/server");
+        assert!(request.envs.is_empty());
+
+        let task = build_task(
+            &["build", "-p", "/tmp/out"],
+            &[(RUN_STEP_ENV, "server")],
+            "/work/app",
+        );
+        let request = launch_request(&mut extension, task);
+        assert_eq!(request.program, "/tmp/out/bin/server");
+    }
+}
@@ -0,0 +1,171 @@
+//! Reading toolchain information from `zig env`.
+
+use zed_extension_api::{self as zed, serde_json, Result};
+
+/// The parts of `zig env` the extension cares about.
+#[derive(Debug, Default, Clone)]
+pub struct ZigEnv {
+    pub zig_exe: Option<String>,
+    pub lib_dir: Option<String>,
+    pub std_dir: Option<String>,
+    pub global_cache_dir: Option<String>,
+    pub version: Option<String>,
+}
+
+impl ZigEnv {
+    /// Parses the output of `zig env`, which is JSON up to Zig 0.14 and ZON from 0.15 on.
+    pub fn parse(output: &str) -> Option<Self> {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
+            let field = |key: &str| json.get(key).and_then(|v| v.as_str()).map(String::from);
+            return Some(Self {
+                zig_exe: field("zig_exe"),
+                lib_dir: field("lib_dir"),
+                std_dir: field("std_dir"),
+                global_cache_dir: field("global_cache_dir"),
+                version: field("version"),
+            });
+        }
+
+        // Only the top level `.key = "value",` lines are needed, so there is no need for a
+        // full ZON parser. Nested objects like `.env` are indented further and skipped.
+        let mut env = Self::default();
+        let mut found = false;
+        for line in output.lines() {
+            let Some(line) = line.strip_prefix("    .") else {
+                continue;
+            };
+            let Some((key, value)) = line.split_once(" = ") else {
+                continue;
+            };
+            let Some(value) = parse_zon_string(value.trim_end().trim_end_matches(',')) else {
+                continue;
+            };
+            let slot = match key {
+                "zig_exe" => &mut env.zig_exe,
+                "lib_dir" => &mut env.lib_dir,
+                "std_dir" => &mut env.std_dir,
+                "global_cache_dir" => &mut env.global_cache_dir,
+                "version" => &mut env.version,
+                _ => continue,
+            };
+            *slot = Some(value);
+            found = true;
+        }
+        found.then_some(env)
+    }
+}
+
+/// Runs `zig env`, falling back to `zig version` when that fails or can't be parsed.
+pub fn detect(zig: &str) -> Result<ZigEnv> {
+    let env = zed::process::Command::new(zig)
+        .arg("env")
+        .output()
+        .ok()
+        .filter(|output| output.status == Some(0))
+        .and_then(|output| ZigEnv::parse(&String::from_utf8_lossy(&output.stdout)));
+    if let Some(env) = env {
+        return Ok(env);
+    }
+
+    let output = zed::process::Command::new(zig).arg("version").output()?;
+    if output.status != Some(0) {
+        return Err(format!(
+            "`{zig} version` exited with {:?}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(ZigEnv {
+        version: Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        ..ZigEnv::default()
+    })
+}
+
+/// Parses a ZON string literal, handling the escapes Zig emits for paths.
+fn parse_zon_string(literal: &str) -> Option<String> {
+    let literal = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            c => value.push(c),
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_json_env() {
+        let output = r#"{
+ "zig_exe": "/usr/local/zig/zig",
+ "lib_dir": "/usr/local/zig/lib",
+ "std_dir": "/usr/local/zig/lib/std",
+ "global_cache_dir": "/home/user/.cache/zig",
+ "version": "0.14.1",
+ "target": "x86_64-linux.6.8...6.8-gnu.2.39",
+ "env": {
+  "ZIG_GLOBAL_CACHE_DIR": null
+ }
+}
+"#;
+        let env = ZigEnv::parse(output).unwrap();
+        assert_eq!(env.zig_exe.as_deref(), Some("/usr/local/zig/zig"));
+        assert_eq!(env.lib_dir.as_deref(), Some("/usr/local/zig/lib"));
+        assert_eq!(env.std_dir.as_deref(), Some("/usr/local/zig/lib/std"));
+        assert_eq!(
+            env.global_cache_dir.as_deref(),
+            Some("/home/user/.cache/zig")
+        );
+        assert_eq!(env.version.as_deref(), Some("0.14.1"));
+    }
+
+    #[test]
+    fn parses_zon_env() {
+        let output = r#".{
+    .zig_exe = "/opt/zig/zig",
+    .lib_dir = "/opt/zig/lib",
+    .std_dir = "/opt/zig/lib/std",
+    .global_cache_dir = "/home/user/.cache/zig",
+    .version = "0.15.0-dev.1034+bd97b6618",
+    .target = "x86_64-linux.6.8...6.8-gnu.2.39",
+    .env = .{
+        .ZIG_GLOBAL_CACHE_DIR = null,
+        .version = "nested",
+    },
+}
+"#;
+        let env = ZigEnv::parse(output).unwrap();
+        assert_eq!(env.zig_exe.as_deref(), Some("/opt/zig/zig"));
+        assert_eq!(env.lib_dir.as_deref(), Some("/opt/zig/lib"));
+        assert_eq!(env.std_dir.as_deref(), Some("/opt/zig/lib/std"));
+        assert_eq!(
+            env.global_cache_dir.as_deref(),
+            Some("/home/user/.cache/zig")
+        );
+        assert_eq!(env.version.as_deref(), Some("0.15.0-dev.1034+bd97b6618"));
+    }
+
+    #[test]
+    fn parses_zon_escapes() {
+        let output = ".{\r\n    .lib_dir = \"C:\\\\Program Files\\\\zig\\\\lib\",\r\n}\r\n";
+        let env = ZigEnv::parse(output).unwrap();
+        assert_eq!(env.lib_dir.as_deref(), Some(r"C:\Program Files\zig\lib"));
+    }
+
+    #[test]
+    fn rejects_unrelated_output() {
+        assert!(ZigEnv::parse("").is_none());
+        assert!(ZigEnv::parse("error: unknown command: env\n").is_none());
+    }
+}
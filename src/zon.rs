@@ -0,0 +1,368 @@
+//! A small, forgiving parser for `build.zig.zon` manifests.
+//!
+//! Only the subset of ZON found in manifests is supported: structs, tuples, strings, enum
+//! literals and plain atoms such as numbers. Both the older format with a string `.name` and
+//! the current one with an enum literal `.name`, a `.fingerprint` and nested `.dependencies`
+//! parse. Comments, CRLF line endings and trailing commas are accepted anywhere.
+
+/// A parsed ZON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    /// An enum literal like `.foo`, stored without the leading dot.
+    EnumLiteral(String),
+    Struct(Vec<(String, Value)>),
+    Tuple(Vec<Value>),
+    /// Anything else (numbers, `true`, ...), kept as written.
+    Atom(String),
+}
+
+impl Value {
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The text of a string or enum literal.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) | Value::EnumLiteral(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// The fields of `build.zig.zon` used by the extension.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// The package name, from either `.name = "foo"` or `.name = .foo`.
+    pub name: Option<String>,
+    /// The package fingerprint, as written (e.g. `0xc1ce108124179e16`).
+    pub fingerprint: Option<String>,
+    pub version: Option<String>,
+    pub minimum_zig_version: Option<String>,
+}
+
+impl Manifest {
+    pub fn parse(source: &str) -> Option<Self> {
+        let root = parse(source)?;
+        let field = |name: &str| root.field(name).and_then(Value::as_str).map(String::from);
+        Some(Self {
+            name: field("name"),
+            fingerprint: match root.field("fingerprint") {
+                Some(Value::Atom(fingerprint)) => Some(fingerprint.clone()),
+                _ => None,
+            },
+            version: field("version"),
+            minimum_zig_version: field("minimum_zig_version"),
+        })
+    }
+}
+
+/// Parses a complete ZON document.
+pub fn parse(source: &str) -> Option<Value> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+    };
+    let value = parser.value()?;
+    parser.next().is_none().then_some(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    LBrace,
+    RBrace,
+    Equals,
+    Comma,
+    Identifier(String),
+    String(String),
+    Atom(String),
+}
+
+fn tokenize(source: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\\' if chars.peek() == Some(&'\\') => {
+                // A line of a multiline string literal runs until the end of the line.
+                chars.next();
+                let mut line = String::new();
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+                let line = line.strip_suffix('\r').unwrap_or(&line).to_string();
+                match tokens.last_mut() {
+                    Some(Token::String(previous)) => {
+                        previous.push('\n');
+                        previous.push_str(&line);
+                    }
+                    _ => tokens.push(Token::String(line)),
+                }
+            }
+            '.' => tokens.push(Token::Dot),
+            '{' => tokens.push(Token::LBrace),
+            '}' => tokens.push(Token::RBrace),
+            '=' => tokens.push(Token::Equals),
+            ',' => tokens.push(Token::Comma),
+            '"' => tokens.push(Token::String(string_literal(&mut chars)?)),
+            '@' if chars.peek() == Some(&'"') => {
+                chars.next();
+                tokens.push(Token::Identifier(string_literal(&mut chars)?));
+            }
+            '\'' => {
+                let mut atom = String::from(c);
+                while let Some(c) = chars.next() {
+                    atom.push(c);
+                    if c == '\\' {
+                        atom.push(chars.next()?);
+                    } else if c == '\'' {
+                        break;
+                    }
+                }
+                tokens.push(Token::Atom(atom));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '-' || c == '+' => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    // Numbers such as `1.5` or `0x1f` are kept in one piece.
+                    let number_dot = c == '.' && word.starts_with(|c: char| c.is_ascii_digit());
+                    if c.is_alphanumeric() || c == '_' || number_dot {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if word.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                    tokens.push(Token::Identifier(word));
+                } else {
+                    tokens.push(Token::Atom(word));
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+/// Reads a string literal after its opening quote.
+fn string_literal(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'x' => {
+                    let hex: String = [chars.next()?, chars.next()?].iter().collect();
+                    value.push(char::from(u8::from_str_radix(&hex, 16).ok()?));
+                }
+                'u' => {
+                    if chars.next()? != '{' {
+                        return None;
+                    }
+                    let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.peek(0) == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        match self.next()? {
+            Token::String(value) => Some(Value::String(value)),
+            Token::Atom(value) | Token::Identifier(value) => Some(Value::Atom(value)),
+            Token::Dot => match self.next()? {
+                Token::Identifier(value) => Some(Value::EnumLiteral(value)),
+                Token::LBrace => self.aggregate(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses the inside of `.{ ... }`, which is a struct when it starts with `.field =`.
+    fn aggregate(&mut self) -> Option<Value> {
+        let is_struct = matches!(
+            (self.peek(0), self.peek(1), self.peek(2)),
+            (
+                Some(Token::Dot),
+                Some(Token::Identifier(_)),
+                Some(Token::Equals)
+            )
+        );
+        if is_struct {
+            let mut fields = Vec::new();
+            while !self.eat(&Token::RBrace) {
+                if !self.eat(&Token::Dot) {
+                    return None;
+                }
+                let Some(Token::Identifier(name)) = self.next() else {
+                    return None;
+                };
+                if !self.eat(&Token::Equals) {
+                    return None;
+                }
+                fields.push((name, self.value()?));
+                if !self.eat(&Token::Comma) && self.peek(0) != Some(&Token::RBrace) {
+                    return None;
+                }
+            }
+            Some(Value::Struct(fields))
+        } else {
+            let mut items = Vec::new();
+            while !self.eat(&Token::RBrace) {
+                items.push(self.value()?);
+                if !self.eat(&Token::Comma) && self.peek(0) != Some(&Token::RBrace) {
+                    return None;
+                }
+            }
+            Some(Value::Tuple(items))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_crlf_line_endings() {
+        let source = ".{\r\n    .name = \"demo\",\r\n    .version = \"0.1.0\",\r\n    .paths = .{\r\n        \"build.zig\",\r\n        \"src\",\r\n    },\r\n}\r\n";
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("demo"));
+        assert_eq!(manifest.version.as_deref(), Some("0.1.0"));
+    }
+
+    #[test]
+    fn ignores_commented_out_fields() {
+        let source = r#"// A package named "demo".
+.{
+    // .name = "old_name",
+    .name = "demo", // renamed in 0.2.0
+    //.minimum_zig_version = "0.11.0",
+    .version = "0.2.0",
+    .paths = .{""},
+}
+"#;
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("demo"));
+        assert_eq!(manifest.version.as_deref(), Some("0.2.0"));
+        assert_eq!(manifest.minimum_zig_version, None);
+    }
+
+    #[test]
+    fn accepts_trailing_commas() {
+        let source = r#".{ .name = "demo", .version = "0.1.0", .paths = .{ "src", }, }"#;
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("demo"));
+
+        let source = r#".{ .name = "demo", .version = "0.1.0" }"#;
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("demo"));
+    }
+
+    #[test]
+    fn parses_current_format() {
+        let source = r#".{
+    .name = .demo,
+    .version = "0.0.0",
+    .fingerprint = 0xc1ce108124179e16,
+    .minimum_zig_version = "0.14.0",
+    .dependencies = .{
+        .zap = .{
+            .url = "git+https://github.com/zigzap/zap#76679f308c702cd8880201e6e93914e1d836a54b",
+            .hash = "zap-0.9.1-GoeB8xCEJABLgoiZjWZMMT5TsoZ5OO2EToqWJ1MEAbnp",
+            .lazy = true,
+        },
+        .local = .{ .path = "../local" },
+    },
+    .paths = .{
+        "build.zig",
+        "build.zig.zon",
+        "src",
+    },
+}
+"#;
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                name: Some("demo".into()),
+                fingerprint: Some("0xc1ce108124179e16".into()),
+                version: Some("0.0.0".into()),
+                minimum_zig_version: Some("0.14.0".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_quoted_identifiers_and_multiline_strings() {
+        let source = r#".{
+    .name = .@"my-package",
+    .version = "1.0.0",
+    .description =
+        \\A package
+        \\with a long description.
+    ,
+    .paths = .{""},
+}
+"#;
+        let root = parse(source).unwrap();
+        assert_eq!(
+            root.field("description").and_then(Value::as_str),
+            Some("A package\nwith a long description.")
+        );
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.name.as_deref(), Some("my-package"));
+        assert_eq!(manifest.fingerprint, None);
+    }
+}